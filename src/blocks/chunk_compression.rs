@@ -0,0 +1,239 @@
+use zune_inflate::DeflateDecoder;
+
+
+// Compression types stored in the fifth byte of each chunk in a region file.
+// https://minecraft.wiki/w/Region_file_format#Payload
+pub const COMPRESSION_GZIP: u8 = 1;
+pub const COMPRESSION_ZLIB: u8 = 2;
+pub const COMPRESSION_NONE: u8 = 3;
+pub const COMPRESSION_LZ4: u8 = 4;
+pub const COMPRESSION_CUSTOM: u8 = 127;
+
+
+// LZ4 compressed chunks are written by lz4-java's LZ4BlockOutputStream, which wraps
+// raw LZ4 blocks in its own framing rather than the standard LZ4 frame format.
+// Each block has a 21 byte header: the magic bytes, a token byte, the compressed length,
+// the decompressed length and a checksum, with the lengths and checksum little-endian.
+// The low four bits of the token are the compression level, which sets the largest
+// decompressed length a block can have to 1 << (10 + level), at most 32 MiB.
+const LZ4_BLOCK_MAGIC: &[u8] = b"LZ4Block";
+const LZ4_BLOCK_HEADER_LENGTH: usize = LZ4_BLOCK_MAGIC.len() + 13;
+const LZ4_BLOCK_COMPRESSION_LEVEL_BASE: u8 = 10;
+const LZ4_BLOCK_METHOD_RAW: u8 = 0x10;
+const LZ4_BLOCK_METHOD_LZ4: u8 = 0x20;
+
+
+pub fn decompress_chunk_data(compression_type: u8, compressed_data: &[u8]) -> Result<Vec<u8>, String> {
+  match compression_type {
+    COMPRESSION_GZIP => {
+      let mut decoder = DeflateDecoder::new(compressed_data);
+      decoder.decode_gzip().map_err(|err| format!("gzip error: {:?}", err))
+    },
+    COMPRESSION_ZLIB => {
+      let mut decoder = DeflateDecoder::new(compressed_data);
+      decoder.decode_zlib().map_err(|err| format!("zlib error: {:?}", err))
+    },
+    COMPRESSION_NONE => {
+      Ok(compressed_data.to_vec())
+    },
+    COMPRESSION_LZ4 => {
+      decode_lz4_blocks(compressed_data)
+    },
+    COMPRESSION_CUSTOM => {
+      // A custom compression type is followed by the namespaced ID of the compression algorithm.
+      let algorithm = read_custom_algorithm_name(compressed_data);
+      Err(format!("unsupported custom compression type {:?}", algorithm))
+    },
+    _ => {
+      Err(format!("unknown compression type {}", compression_type))
+    }
+  }
+}
+
+
+fn read_custom_algorithm_name(data: &[u8]) -> String {
+  if data.len() < 2 {
+    return "".to_string();
+  }
+
+  let name_length = u16::from_be_bytes([data[0], data[1]]) as usize;
+  let name_end = std::cmp::min(2 + name_length, data.len());
+
+  String::from_utf8_lossy(&data[2..name_end]).to_string()
+}
+
+
+fn read_le_u32(data: &[u8], index: usize) -> usize {
+  u32::from_le_bytes(data[index..index + 4].try_into().unwrap()) as usize
+}
+
+
+fn decode_lz4_blocks(data: &[u8]) -> Result<Vec<u8>, String> {
+  let mut decompressed_data: Vec<u8> = Vec::new();
+  let mut index = 0;
+
+  loop {
+    let header_end = index + LZ4_BLOCK_HEADER_LENGTH;
+
+    if header_end > data.len() {
+      return Err("lz4 error: truncated block header".to_string());
+    }
+
+    if &data[index..index + LZ4_BLOCK_MAGIC.len()] != LZ4_BLOCK_MAGIC {
+      return Err("lz4 error: missing block magic".to_string());
+    }
+
+    let token = data[index + LZ4_BLOCK_MAGIC.len()];
+    let compressed_length = read_le_u32(data, index + LZ4_BLOCK_MAGIC.len() + 1);
+    let decompressed_length = read_le_u32(data, index + LZ4_BLOCK_MAGIC.len() + 5);
+
+    // Check the decompressed length before anything is allocated for it,
+    // so that a corrupt header can't ask for up to 4 GiB.
+    let max_block_length = 1 << (LZ4_BLOCK_COMPRESSION_LEVEL_BASE + (token & 0x0f));
+    if decompressed_length > max_block_length {
+      return Err(format!("lz4 error: block length {} is more than the block size {}", decompressed_length, max_block_length));
+    }
+
+    // A block with zero decompressed length marks the end of the stream.
+    if decompressed_length == 0 {
+      return Ok(decompressed_data);
+    }
+
+    let block_end = header_end + compressed_length;
+
+    if block_end > data.len() {
+      return Err("lz4 error: truncated block".to_string());
+    }
+
+    let block = &data[header_end..block_end];
+
+    match token & 0xf0 {
+      LZ4_BLOCK_METHOD_RAW => {
+        if compressed_length != decompressed_length {
+          return Err(format!("lz4 error: raw block length {} doesn't match its decompressed length {}", compressed_length, decompressed_length));
+        }
+        decompressed_data.extend_from_slice(block);
+      },
+      LZ4_BLOCK_METHOD_LZ4 => {
+        let decompressed_block = lz4_flex::block::decompress(block, decompressed_length)
+          .map_err(|err| format!("lz4 error: {}", err))?;
+        if decompressed_block.len() != decompressed_length {
+          return Err(format!("lz4 error: block decompressed to {} bytes instead of {}", decompressed_block.len(), decompressed_length));
+        }
+        decompressed_data.extend_from_slice(&decompressed_block);
+      },
+      method => {
+        return Err(format!("lz4 error: unknown block method {:#x}", method));
+      }
+    }
+
+    index = block_end;
+
+    // Some writers omit the end marker block.
+    if index == data.len() {
+      return Ok(decompressed_data);
+    }
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // The compression level of the blocks written by Minecraft, for 64 KiB blocks.
+  const TEST_COMPRESSION_LEVEL: u8 = 6;
+
+  // An LZ4Block header, with a zero checksum, since the checksum isn't checked.
+  fn lz4_block_header(method: u8, compressed_length: u32, decompressed_length: u32) -> Vec<u8> {
+    let mut header = LZ4_BLOCK_MAGIC.to_vec();
+    header.push(method | TEST_COMPRESSION_LEVEL);
+    header.extend_from_slice(&compressed_length.to_le_bytes());
+    header.extend_from_slice(&decompressed_length.to_le_bytes());
+    header.extend_from_slice(&0u32.to_le_bytes());
+    header
+  }
+
+  fn lz4_block(method: u8, decompressed_data: &[u8]) -> Vec<u8> {
+    let block = if method == LZ4_BLOCK_METHOD_LZ4 {
+      lz4_flex::block::compress(decompressed_data)
+    } else {
+      decompressed_data.to_vec()
+    };
+
+    let mut data = lz4_block_header(method, block.len() as u32, decompressed_data.len() as u32);
+    data.extend_from_slice(&block);
+    data
+  }
+
+  fn end_block() -> Vec<u8> {
+    lz4_block_header(LZ4_BLOCK_METHOD_RAW, 0, 0)
+  }
+
+  #[test]
+  fn decodes_raw_block() {
+    let mut data = lz4_block(LZ4_BLOCK_METHOD_RAW, b"not compressed");
+    data.extend(end_block());
+
+    assert_eq!(decompress_chunk_data(COMPRESSION_LZ4, &data), Ok(b"not compressed".to_vec()));
+  }
+
+  #[test]
+  fn decodes_lz4_blocks() {
+    let first: Vec<u8> = b"minecraft:rail ".repeat(100);
+    let second: Vec<u8> = b"minecraft:powered_rail ".repeat(50);
+
+    let mut data = lz4_block(LZ4_BLOCK_METHOD_LZ4, &first);
+    data.extend(lz4_block(LZ4_BLOCK_METHOD_LZ4, &second));
+    data.extend(end_block());
+
+    assert_eq!(decompress_chunk_data(COMPRESSION_LZ4, &data), Ok([first.clone(), second.clone()].concat()));
+
+    // Without the end marker block.
+    let data = lz4_block(LZ4_BLOCK_METHOD_LZ4, &first);
+    assert_eq!(decompress_chunk_data(COMPRESSION_LZ4, &data), Ok(first));
+  }
+
+  #[test]
+  fn rejects_bad_magic() {
+    let mut data = lz4_block(LZ4_BLOCK_METHOD_RAW, b"data");
+    data[0] = b'X';
+
+    assert!(decode_lz4_blocks(&data).is_err());
+  }
+
+  #[test]
+  fn rejects_truncated_header() {
+    let data = lz4_block(LZ4_BLOCK_METHOD_RAW, b"data");
+
+    assert!(decode_lz4_blocks(&data[..LZ4_BLOCK_HEADER_LENGTH - 1]).is_err());
+    assert!(decode_lz4_blocks(&data[..LZ4_BLOCK_HEADER_LENGTH + 2]).is_err());
+  }
+
+  #[test]
+  fn rejects_mismatched_lengths() {
+    // A raw block whose decompressed length doesn't match its length.
+    let mut data = lz4_block_header(LZ4_BLOCK_METHOD_RAW, 4, 5);
+    data.extend_from_slice(b"data");
+    assert!(decode_lz4_blocks(&data).is_err());
+
+    // An LZ4 block that decompresses to fewer bytes than its header says.
+    let block = lz4_flex::block::compress(b"data");
+    let mut data = lz4_block_header(LZ4_BLOCK_METHOD_LZ4, block.len() as u32, 5);
+    data.extend_from_slice(&block);
+    assert!(decode_lz4_blocks(&data).is_err());
+  }
+
+  #[test]
+  fn rejects_lengths_over_block_size() {
+    // Checked before decompressing, so that nothing is allocated for it.
+    let mut data = lz4_block_header(LZ4_BLOCK_METHOD_LZ4, 4, u32::MAX);
+    data.extend_from_slice(b"data");
+    assert!(decode_lz4_blocks(&data).is_err());
+
+    let max_block_length = 1u32 << (LZ4_BLOCK_COMPRESSION_LEVEL_BASE + TEST_COMPRESSION_LEVEL);
+    let mut data = lz4_block_header(LZ4_BLOCK_METHOD_RAW, max_block_length + 1, max_block_length + 1);
+    data.extend(vec![0; max_block_length as usize + 1]);
+    assert!(decode_lz4_blocks(&data).is_err());
+  }
+}
@@ -1,61 +1,160 @@
 use std::collections::{HashSet, HashMap};
+use std::thread;
 
 use crate::common::{
-  RegionCoords,
   ChunkCoords,
+  ChunkWarning,
   BlockCoords,
   Direction,
-  Region,
   Block,
   BlockID,
 };
-use crate::common::{block_coords_to_chunk_coords};
+use crate::common::{
+  block_coords_to_chunk_coords,
+  chunk_coords_to_region_coords,
+  move_coords,
+  nether_portal_destination
+};
+
+use crate::blocks::region_cache::RegionCache;
+use crate::blocks::find_chunk_blocks::CompressedChunk;
+use crate::blocks::find_chunk_blocks::{read_chunk_data, decode_chunk_blocks};
+
 
-use crate::blocks::find_chunk_blocks::find_chunk_blocks;
+// The number of chunks in a region, which is the most chunks that are read before they're decoded.
+const MAX_FRONTIER_BATCH_LEN: usize = 1024;
 
 
+// Chunks are found by a flood fill, starting from the seed chunks and following rails
+// into adjacent chunks. Each frontier of newly found chunks is read from the region files
+// serially, then decoded in parallel, then processed in the same order it was read in, so
+// that the order of the blocks, and thus the generated functions, doesn't depend on threading.
+// Large frontiers, such as all of the chunks of a scanned area, are read in batches, so that
+// only one batch of compressed chunk data is in memory at a time.
 pub fn find_blocks(
-  starting_chunk_coords: ChunkCoords,
+  seed_chunk_coords: Vec<ChunkCoords>,
   world_dir: &String,
-  ties_map: &HashMap<BlockCoords, (BlockCoords, Direction, Direction)>
-) -> (Vec<Block>, Vec<(ChunkCoords, usize)>) {
+  read_back_text: bool,
+  ties_map: &HashMap<BlockCoords, (BlockCoords, Direction, Direction)>,
+  region_cache: &mut RegionCache
+) -> (Vec<Block>, Vec<(ChunkCoords, usize)>, Vec<ChunkWarning>) {
   let mut all_blocks: Vec<Block> = Vec::new();
 
   let mut chunks: Vec<(ChunkCoords, usize)> = Vec::new();
 
-  let mut chunk_coords_to_process = vec![starting_chunk_coords];
+  // Chunks that couldn't be read are skipped, and reported at the end of the scan.
+  let mut chunk_warnings: Vec<ChunkWarning> = Vec::new();
 
   let mut processed_chunk_coords: HashSet<ChunkCoords> = HashSet::new();
 
+  let mut portal_search = PortalSearch::default();
+
+  let mut frontier_chunk_coords: Vec<ChunkCoords> = seed_chunk_coords
+    .into_iter()
+    .filter(|chunk_coords| processed_chunk_coords.insert(*chunk_coords))
+    .collect();
+
   let mut chunk_num: u32 = 0;
 
-  let mut region_cache: HashMap<RegionCoords, Region> = HashMap::new();
-  
-  while let Some(chunk_coords) = chunk_coords_to_process.pop() {
-    if !processed_chunk_coords.contains(&chunk_coords) {
-      processed_chunk_coords.insert(chunk_coords);
-      
-      chunk_num += 1;
-
-      let mut blocks = find_chunk_blocks(
-        chunk_coords,
-        chunk_num,
-        world_dir,
-        &mut region_cache
-      );
+  let num_threads = thread::available_parallelism().map_or(1, |n| n.get());
+
+  while !frontier_chunk_coords.is_empty() {
+    // Sorting the frontier by region keeps reads from the same region together.
+    frontier_chunk_coords.sort_by_key(|chunk_coords| (chunk_coords_to_region_coords(*chunk_coords), *chunk_coords));
+
+    let mut next_frontier_chunk_coords = Vec::new();
+
+    for frontier_batch_chunk_coords in frontier_chunk_coords.chunks(MAX_FRONTIER_BATCH_LEN) {
+      let mut frontier_chunk_data = Vec::new();
 
-      if blocks.len() > 0 {
-        chunks.push((chunk_coords, blocks.len()));
+      for chunk_coords in frontier_batch_chunk_coords {
+        chunk_num += 1;
+
+        frontier_chunk_data.push(
+          read_chunk_data(
+            *chunk_coords,
+            chunk_num,
+            world_dir,
+            region_cache
+          )
+        );
       }
 
-      let mut more_chunk_coords_to_process = find_more_chunk_coords_to_process(&blocks, &ties_map);
-      chunk_coords_to_process.append(&mut more_chunk_coords_to_process);
-    
-      all_blocks.append(&mut blocks);
+      let frontier_blocks = decode_frontier_blocks(
+        frontier_batch_chunk_coords,
+        frontier_chunk_data,
+        read_back_text,
+        num_threads
+      );
+
+      for (chunk_coords, blocks_result) in frontier_batch_chunk_coords.iter().zip(frontier_blocks) {
+        let mut blocks = match blocks_result {
+          Ok(blocks) => blocks,
+          Err(err) => {
+            chunk_warnings.push((*chunk_coords, err));
+            continue;
+          }
+        };
+
+        if !blocks.is_empty() {
+          chunks.push((*chunk_coords, blocks.len()));
+        }
+
+        let more_chunk_coords_to_process = find_more_chunk_coords_to_process(&blocks, ties_map)
+          .into_iter()
+          .chain(find_portal_destination_chunk_coords(&blocks, &mut portal_search));
+
+        for more_chunk_coords in more_chunk_coords_to_process {
+          if processed_chunk_coords.insert(more_chunk_coords) {
+            next_frontier_chunk_coords.push(more_chunk_coords);
+          }
+        }
+
+        all_blocks.append(&mut blocks);
+      }
     }
+
+    frontier_chunk_coords = next_frontier_chunk_coords;
   }
   
-  (all_blocks, chunks)
+  (all_blocks, chunks, chunk_warnings)
+}
+
+
+// Decodes the chunks of a frontier using up to num_threads threads, each of which
+// decodes a contiguous slice of the frontier. The results are in frontier order.
+fn decode_frontier_blocks(
+  frontier_chunk_coords: &[ChunkCoords],
+  frontier_chunk_data: Vec<Result<Option<CompressedChunk>, String>>,
+  read_back_text: bool,
+  num_threads: usize
+) -> Vec<Result<Vec<Block>, String>> {
+  let mut work: Vec<(ChunkCoords, Result<Option<CompressedChunk>, String>)> =
+    frontier_chunk_coords.iter().copied().zip(frontier_chunk_data).collect();
+
+  let slice_len = work.len().div_ceil(num_threads).max(1);
+
+  thread::scope(|scope| {
+    let handles: Vec<_> = work
+      .chunks_mut(slice_len)
+      .map(|slice| {
+        scope.spawn(move || {
+          slice
+            .iter_mut()
+            .map(|(chunk_coords, chunk_data_result)| {
+              let chunk_data = std::mem::replace(chunk_data_result, Ok(None))?;
+              decode_chunk_blocks(*chunk_coords, chunk_data, read_back_text)
+            })
+            .collect::<Vec<_>>()
+        })
+      })
+      .collect();
+
+    handles
+      .into_iter()
+      .flat_map(|handle| handle.join().unwrap())
+      .collect()
+  })
 }
 
 
@@ -67,6 +166,67 @@ fn is_block_at_chunk_edge(block: &Block) -> (bool, bool, bool, bool) {
 }
 
 
+// The nether portal blocks found so far, and the blocks that straight rails found so far lead
+// into. A rail and the portal it leads into can be in different chunks, so they're kept
+// until the whole rail system has been found.
+#[derive(Default)]
+struct PortalSearch {
+  portal_coords: HashSet<BlockCoords>,
+  rail_end_coords: HashSet<BlockCoords>,
+}
+
+
+// Returns the chunks in the other realm where the game would look for the portal that a nether
+// portal leads to, so that rails on the other side of the portal can be found. Only nether portal
+// blocks that a straight rail leads into are followed, the same as for portal ties, so that the
+// search doesn't spread through portals that aren't part of the rail system.
+fn find_portal_destination_chunk_coords(blocks: &[Block], portal_search: &mut PortalSearch) -> Vec<ChunkCoords> {
+  let mut rail_portal_coords: Vec<BlockCoords> = Vec::new();
+
+  for block in blocks {
+    if block.is_nether_portal() {
+      portal_search.portal_coords.insert(block.coords);
+
+      if portal_search.rail_end_coords.contains(&block.coords) {
+        rail_portal_coords.push(block.coords);
+      }
+    } else if let Some(directions) = block.straight_rail_directions() {
+      for direction in directions {
+        let rail_end_coords = move_coords(block.coords, direction);
+        portal_search.rail_end_coords.insert(rail_end_coords);
+
+        if portal_search.portal_coords.contains(&rail_end_coords) {
+          rail_portal_coords.push(rail_end_coords);
+        }
+      }
+    }
+  }
+
+  let mut destination_chunk_coords: Vec<ChunkCoords> = Vec::new();
+
+  for portal_coords in rail_portal_coords {
+    if let Some((destination_coords, search_radius)) = nether_portal_destination(portal_coords) {
+      let (destination_x, destination_y, destination_z, destination_realm) = destination_coords;
+
+      let (min_chunk_x, min_chunk_z, _) = block_coords_to_chunk_coords(
+        (destination_x - search_radius, destination_y, destination_z - search_radius, destination_realm)
+      );
+      let (max_chunk_x, max_chunk_z, _) = block_coords_to_chunk_coords(
+        (destination_x + search_radius, destination_y, destination_z + search_radius, destination_realm)
+      );
+
+      for chunk_x in min_chunk_x..=max_chunk_x {
+        for chunk_z in min_chunk_z..=max_chunk_z {
+          destination_chunk_coords.push((chunk_x, chunk_z, destination_realm));
+        }
+      }
+    }
+  }
+
+  destination_chunk_coords
+}
+
+
 fn find_more_chunk_coords_to_process(
   blocks: &Vec<Block>,
   ties_map: &HashMap<BlockCoords, (BlockCoords, Direction, Direction)>
@@ -80,6 +240,7 @@ fn find_more_chunk_coords_to_process(
     if block.is_sign() {
       sign_block_coords.insert(block.coords);
     }
+
   }
 
   for block in blocks {
@@ -1,25 +1,33 @@
-use std::collections::HashMap;
+use std::fs::File;
+use std::io::Read;
 
-use zune_inflate::DeflateDecoder;
-
-use crate::common::{ChunkCoords, Block, RegionCoords, Region};
+use crate::common::{ChunkCoords, Block};
 use crate::common::{
   chunk_coords_to_region_coords,
   read_three_byte_size,
   read_four_byte_size
 };
 
-use crate::blocks::region_cache::update_region_cache;
+use crate::blocks::region_cache::RegionCache;
+use crate::blocks::region_cache::{update_region_cache, region_dir_path};
+
+use crate::blocks::chunk_compression::decompress_chunk_data;
 
 use crate::blocks::nbt_blocks::find_chunk_nbt_blocks;
 
 
-pub fn find_chunk_blocks(
+// The compression type and the compressed data of a chunk.
+pub type CompressedChunk = (u8, Vec<u8>);
+
+
+// Returns the compression type and the compressed chunk data, or None when the chunk hasn't
+// been generated. Reading is done serially, since it goes through the region cache.
+pub fn read_chunk_data(
   chunk_coords: ChunkCoords,
   chunk_num: u32,
   world_dir: &String,
-  region_cache: &mut HashMap<RegionCoords, Region>
-) -> Vec<Block> {
+  region_cache: &mut RegionCache
+) -> Result<Option<CompressedChunk>, String> {
   let region_coords = chunk_coords_to_region_coords(chunk_coords);
 
   update_region_cache(
@@ -30,36 +38,124 @@ pub fn find_chunk_blocks(
   );
 
   match region_cache.get(&region_coords) {
-    Some(region) => {
-      if let Some(chunk_nbt) = extract_chunk_nbt(chunk_coords, &region.data) {
-        find_chunk_nbt_blocks(chunk_coords, chunk_nbt)
-      } else {
-        vec![]
-      }
+    Some(region) => match &region.read_error {
+      Some(read_error) => Err(read_error.clone()),
+      None => extract_chunk_data(chunk_coords, &region.data, world_dir),
     },
+    None => Ok(None)
+  }
+}
+
+
+// Decompresses the chunk data and finds the blocks of interest in it.
+// Decoding is independent of other chunks, so it can be done in parallel.
+pub fn decode_chunk_blocks(
+  chunk_coords: ChunkCoords,
+  chunk_data: Option<CompressedChunk>,
+  read_back_text: bool
+) -> Result<Vec<Block>, String> {
+  let (compression_type, compressed_chunk_nbt) = match chunk_data {
+    Some(chunk_data) => chunk_data,
     None => {
-      vec![]
+      return Ok(vec![]);
     }
-  }    
+  };
+
+  let chunk_nbt = match decompress_chunk_data(compression_type, &compressed_chunk_nbt) {
+    Ok(chunk_nbt) => chunk_nbt,
+    Err(err) => {
+      return Err(format!("can't decompress chunk: {}", err));
+    }
+  };
+
+  find_chunk_nbt_blocks(chunk_coords, chunk_nbt, read_back_text)
 }
 
 
-fn extract_chunk_nbt(chunk_coords: ChunkCoords, region_data: &Vec<u8>) -> Option<Vec<u8>> {
+// The region file header has 1024 chunk locations followed by 1024 chunk timestamps.
+const REGION_HEADER_LENGTH: usize = 8192;
+
+// Chunks too large to fit in a region file are stored in a separate file, next to the region file.
+const EXTERNAL_CHUNK_FLAG: u8 = 0x80;
+
+
+fn read_external_chunk_data(chunk_coords: ChunkCoords, world_dir: &String) -> Result<Vec<u8>, String> {
+  let (chunk_x, chunk_z, realm) = chunk_coords;
+
+  let external_chunk_path = format!("{}/c.{}.{}.mcc", region_dir_path(realm, world_dir), chunk_x, chunk_z);
+
+  let mut data = Vec::new();
+
+  match File::open(&external_chunk_path) {
+    Ok(mut file) => {
+      if let Err(err) = file.read_to_end(&mut data) {
+        return Err(format!("can't read external chunk file {:?}: {}", external_chunk_path, err));
+      }
+    },
+    Err(err) => {
+      return Err(format!("can't open external chunk file {:?}: {}", external_chunk_path, err));
+    }
+  }
+
+  Ok(data)
+}
+
+
+// Returns None when the chunk hasn't been generated, or an error message
+// when the region file is truncated or the chunk data is missing.
+fn extract_chunk_data(
+  chunk_coords: ChunkCoords,
+  region_data: &[u8],
+  world_dir: &String
+) -> Result<Option<CompressedChunk>, String> {
+  // A missing or zero-length region file is treated as a region with no generated chunks.
+  if region_data.is_empty() {
+    return Ok(None);
+  }
+
+  if region_data.len() < REGION_HEADER_LENGTH {
+    return Err(format!("region file header is truncated to {} bytes", region_data.len()));
+  }
+
   let (chunk_x, chunk_z, _) = chunk_coords;
   let loc_offset = 4 * (chunk_x.rem_euclid(32) + 32 * chunk_z.rem_euclid(32)) as usize;
 
   let chunk_offset = 4096 * read_three_byte_size(region_data, loc_offset);
 
+  if chunk_offset == 0 {
+    return Ok(None);
+  }
+
+  if chunk_offset + 5 > region_data.len() {
+    return Err(format!("chunk offset {} is beyond the end of the region file", chunk_offset));
+  }
+
+  // The chunk length includes the compression type byte that follows it.
   let chunk_length = read_four_byte_size(region_data, chunk_offset);
+  let compression_byte = region_data[chunk_offset + 4];
+  let compression_type = compression_byte & !EXTERNAL_CHUNK_FLAG;
+
+  if chunk_length == 0 {
+    return Err("chunk length is zero".to_string());
+  }
+
+  if compression_byte & EXTERNAL_CHUNK_FLAG != 0 {
+    // The chunk data in the region file is empty, and the whole compressed chunk
+    // is in the external file, compressed with the same compression type.
+    let external_chunk_data = read_external_chunk_data(chunk_coords, world_dir)?;
+    return Ok(Some((compression_type, external_chunk_data)));
+  }
 
   let region_data_start = chunk_offset + 5;
 
-  if chunk_offset != 0 {
-    let compressed_chunk_nbt = &region_data[region_data_start..region_data_start + chunk_length];
-    let mut decoder = DeflateDecoder::new(compressed_chunk_nbt);
-    let chunk_nbt = decoder.decode_zlib().unwrap();
-    return Some(chunk_nbt.to_vec());
+  // Compared without adding to the start, so that a corrupt length can't overflow.
+  if chunk_length - 1 > region_data.len() - region_data_start {
+    return Err(format!("chunk data is truncated, expected {} bytes but found {}",
+                       chunk_length - 1,
+                       region_data.len() - region_data_start));
   }
 
-  None
+  let region_data_end = region_data_start + chunk_length - 1;
+
+  Ok(Some((compression_type, region_data[region_data_start..region_data_end].to_vec())))
 }
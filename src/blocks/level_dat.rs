@@ -0,0 +1,62 @@
+use std::io::Read;
+
+use crate::common::{
+  BlockCoords,
+  Realm
+};
+use crate::common::{
+  open_file,
+  realm_from_str
+};
+
+use crate::blocks::nbt::Nbt;
+use crate::blocks::nbt::read_nbt;
+use crate::blocks::chunk_compression::{decompress_chunk_data, COMPRESSION_GZIP};
+
+
+// Returns the coordinates of the world spawn point from the world's level.dat file.
+// https://minecraft.wiki/w/Java_Edition_level_format#level.dat_format
+pub fn read_spawn_block_coords(world_dir: &String) -> BlockCoords {
+  let level_dat_path = format!("{}/level.dat", world_dir);
+
+  let mut compressed_data = Vec::new();
+
+  if let Err(err) = open_file(&level_dat_path).read_to_end(&mut compressed_data) {
+    exit!("Error reading from file {:?}: {}", level_dat_path, err);
+  }
+
+  let level_nbt = match decompress_chunk_data(COMPRESSION_GZIP, &compressed_data).and_then(|data| read_nbt(&data)) {
+    Ok((_, level_nbt)) => level_nbt,
+    Err(err) => {
+      exit!("Error reading NBT from file {:?}: {}", level_dat_path, err);
+    }
+  };
+
+  match spawn_block_coords(&level_nbt) {
+    Some(spawn_block_coords) => spawn_block_coords,
+    None => {
+      exit!("No spawn point found in file {:?}", level_dat_path);
+    }
+  }
+}
+
+
+fn spawn_block_coords(level_nbt: &Nbt) -> Option<BlockCoords> {
+  let data = level_nbt.get("Data")?;
+
+  // Since 1.21.9 the spawn point is a position and a dimension,
+  // and before that it was always in the Overworld.
+  if let Some(spawn) = data.get("spawn") {
+    if let Some([x, y, z]) = spawn.get("pos").and_then(Nbt::as_int_array).map(|pos| &pos[..]) {
+      let realm = spawn.get("dimension").and_then(Nbt::as_str).map_or(Realm::Overworld, realm_from_str);
+      return Some((*x, *y, *z, realm));
+    }
+  }
+
+  Some((
+    data.get("SpawnX").and_then(Nbt::as_int)?,
+    data.get("SpawnY").and_then(Nbt::as_int).unwrap_or(0),
+    data.get("SpawnZ").and_then(Nbt::as_int)?,
+    Realm::Overworld,
+  ))
+}
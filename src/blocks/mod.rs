@@ -1,5 +1,12 @@
 pub mod find_blocks;
 mod find_chunk_blocks;
-mod region_cache;
+mod chunk_compression;
+pub mod region_cache;
+pub mod scan_chunks;
+pub mod level_dat;
 mod nbt;
+// The NBT writer is only used to put together test chunks so far.
+#[cfg(test)]
+mod nbt_writer;
 mod nbt_blocks;
+mod sign_text;
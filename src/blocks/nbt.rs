@@ -1,152 +1,343 @@
-use std::str;
-use std::collections::HashMap;
+// This is a general purpose NBT API, so not everything in it is used by autorail.
+#![allow(dead_code)]
 
-use crate::common::{
-  read_two_byte_size,
-  read_four_byte_size
-};
+// https://minecraft.wiki/w/NBT_format
 
 
+pub const TAG_END: u8 = 0;
+pub const TAG_BYTE: u8 = 1;
+pub const TAG_SHORT: u8 = 2;
+pub const TAG_INT: u8 = 3;
+pub const TAG_LONG: u8 = 4;
+pub const TAG_FLOAT: u8 = 5;
+pub const TAG_DOUBLE: u8 = 6;
+pub const TAG_BYTE_ARRAY: u8 = 7;
+pub const TAG_STRING: u8 = 8;
+pub const TAG_LIST: u8 = 9;
+pub const TAG_COMPOUND: u8 = 10;
+pub const TAG_INT_ARRAY: u8 = 11;
+pub const TAG_LONG_ARRAY: u8 = 12;
+
+
+// The same limit that Minecraft uses, to avoid overflowing the stack on corrupt data.
+const MAX_DEPTH: usize = 512;
+
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum Nbt {
-  NbtCompound(HashMap<String, Nbt>),
-  NbtList(Vec<Nbt>),
-  
-  // NbtData doesn't preserve tag IDs because we already know
-  // what they must be based on the known chunk NBT structure.
-  NbtData(Vec<u8>)
-}
+  Byte(i8),
+  Short(i16),
+  Int(i32),
+  Long(i64),
+  Float(f32),
+  Double(f64),
+  ByteArray(Vec<i8>),
+  String(String),
 
+  // The tag ID of the list elements is kept so that empty lists keep their type.
+  List(u8, Vec<Nbt>),
 
-pub fn read_chunk_nbt(data: &Vec<u8>) -> Nbt {
-  let (_, _, payload_start_index) = read_nbt_tag(data, 0);
-  let (nbt_compound, _) = read_nbt_compound(data, payload_start_index);
+  // Compound entries are kept in the order they were read, so that
+  // writing a compound produces the same bytes that were read.
+  Compound(Vec<(String, Nbt)>),
 
-  return nbt_compound;
+  IntArray(Vec<i32>),
+  LongArray(Vec<i64>),
 }
 
 
-fn read_nbt_compound(data: &Vec<u8>, payload_start_index: usize) -> (Nbt, usize) {
-  let mut nbt_compound_map: HashMap<String, Nbt> = HashMap::new();
+impl Nbt {
+  pub fn tag_id(&self) -> u8 {
+    match self {
+      Nbt::Byte(_) => TAG_BYTE,
+      Nbt::Short(_) => TAG_SHORT,
+      Nbt::Int(_) => TAG_INT,
+      Nbt::Long(_) => TAG_LONG,
+      Nbt::Float(_) => TAG_FLOAT,
+      Nbt::Double(_) => TAG_DOUBLE,
+      Nbt::ByteArray(_) => TAG_BYTE_ARRAY,
+      Nbt::String(_) => TAG_STRING,
+      Nbt::List(_, _) => TAG_LIST,
+      Nbt::Compound(_) => TAG_COMPOUND,
+      Nbt::IntArray(_) => TAG_INT_ARRAY,
+      Nbt::LongArray(_) => TAG_LONG_ARRAY,
+    }
+  }
 
-  let mut next_start_index = payload_start_index;
+  // Returns the named child of a compound.
+  pub fn get(&self, name: &str) -> Option<&Nbt> {
+    if let Nbt::Compound(entries) = self {
+      for (entry_name, entry) in entries {
+        if entry_name == name {
+          return Some(entry);
+        }
+      }
+    }
+    None
+  }
 
-  loop {
-    let (id, name, child_payload_start_index) = read_nbt_tag(data, next_start_index);
+  // Follows a dot-separated path of compound names and list indices,
+  // e.g. "block_states.palette" or "sections.0.Y".
+  pub fn get_path(&self, path: &str) -> Option<&Nbt> {
+    let mut nbt = self;
 
-    let (child_option, next_index) = get_nbt_child(id, data, child_payload_start_index);
-    if let Some(child) = child_option {
-      next_start_index = next_index;
-      nbt_compound_map.insert(name, child);
-    } else {
-      return (Nbt::NbtCompound(nbt_compound_map), child_payload_start_index);
+    for step in path.split('.') {
+      nbt = match nbt {
+        Nbt::List(_, items) => items.get(step.parse::<usize>().ok()?)?,
+        _ => nbt.get(step)?,
+      };
     }
+
+    Some(nbt)
+  }
+
+  pub fn as_byte(&self) -> Option<i8> {
+    if let Nbt::Byte(v) = self {Some(*v)} else {None}
+  }
+
+  pub fn as_short(&self) -> Option<i16> {
+    if let Nbt::Short(v) = self {Some(*v)} else {None}
+  }
+
+  pub fn as_int(&self) -> Option<i32> {
+    if let Nbt::Int(v) = self {Some(*v)} else {None}
   }
-}
 
+  pub fn as_long(&self) -> Option<i64> {
+    if let Nbt::Long(v) = self {Some(*v)} else {None}
+  }
 
-fn read_nbt_list(data: &Vec<u8>, payload_start_index: usize) -> (Nbt, usize) {
-  let mut nbt_list: Vec<Nbt> = Vec::new();
+  pub fn as_float(&self) -> Option<f32> {
+    if let Nbt::Float(v) = self {Some(*v)} else {None}
+  }
 
-  let id = data[payload_start_index];
-  let nbt_list_length = read_four_byte_size(data, payload_start_index + 1);
-  
-  let mut next_start_index = payload_start_index + 5;
+  pub fn as_double(&self) -> Option<f64> {
+    if let Nbt::Double(v) = self {Some(*v)} else {None}
+  }
 
-  for nbt_list_index in 0..nbt_list_length {
-    let (child_option, next_index) = get_nbt_child(id, data, next_start_index);
-    if let Some(child) = child_option {
-      next_start_index = next_index;
-      nbt_list.insert(nbt_list_index, child);
+  // Returns the value of any of the integer tags, since Minecraft
+  // doesn't always use the same integer type for the same field.
+  pub fn as_integer(&self) -> Option<i64> {
+    match self {
+      Nbt::Byte(v) => Some(*v as i64),
+      Nbt::Short(v) => Some(*v as i64),
+      Nbt::Int(v) => Some(*v as i64),
+      Nbt::Long(v) => Some(*v),
+      _ => None,
     }
   }
 
-  (Nbt::NbtList(nbt_list), next_start_index)
-}
+  pub fn as_str(&self) -> Option<&str> {
+    if let Nbt::String(v) = self {Some(v.as_str())} else {None}
+  }
 
+  pub fn as_list(&self) -> Option<&Vec<Nbt>> {
+    if let Nbt::List(_, v) = self {Some(v)} else {None}
+  }
 
-fn read_nbt_tag(data: &Vec<u8>, index: usize) -> (u8, String, usize) {
-  let tag_id = data[index];
+  pub fn as_compound(&self) -> Option<&Vec<(String, Nbt)>> {
+    if let Nbt::Compound(v) = self {Some(v)} else {None}
+  }
 
-  if tag_id == 0 {
-    return (0, "".to_string(), index + 1);
+  pub fn as_byte_array(&self) -> Option<&Vec<i8>> {
+    if let Nbt::ByteArray(v) = self {Some(v)} else {None}
   }
-  
-  let name_length = read_two_byte_size(data, index + 1);
-  let name_index = index + 3;
-  let payload_start_index = name_index + name_length;
 
-  let name: &str;
-  if let Ok(n) = str::from_utf8(&data[name_index..payload_start_index]) {
-    name = n;
-  } else {
-    name = ""
+  pub fn as_int_array(&self) -> Option<&Vec<i32>> {
+    if let Nbt::IntArray(v) = self {Some(v)} else {None}
   }
 
-  (tag_id, name.to_string(), payload_start_index)
+  pub fn as_long_array(&self) -> Option<&Vec<i64>> {
+    if let Nbt::LongArray(v) = self {Some(v)} else {None}
+  }
 }
 
 
-fn get_nbt_child(id: u8, data: &Vec<u8>, index: usize) -> (Option<Nbt>, usize) {
-    if let Some((nbt_data_length, nbt_data_offset)) = get_nbt_data_length_and_offset(id, data, index) {
+// Reads an uncompressed NBT root tag, returning the root's name and the root tag.
+pub fn read_nbt(data: &[u8]) -> Result<(String, Nbt), String> {
+  let mut reader = NbtReader { data, index: 0 };
 
-      let nbt_data_start = index + nbt_data_offset;
-      let next_index = nbt_data_start + nbt_data_length;
+  let tag_id = reader.read_u8()?;
+  if tag_id == TAG_END {
+    return Err("NBT data has no root tag".to_string());
+  }
 
-      (Some(Nbt::NbtData(data[nbt_data_start..next_index].to_vec())), next_index)
+  let name = reader.read_string()?;
+  let root = reader.read_payload(tag_id, 0)?;
 
-    } else {
+  Ok((name, root))
+}
+
+
+struct NbtReader<'a> {
+  data: &'a [u8],
+  index: usize,
+}
+
+
+impl NbtReader<'_> {
+  fn read_bytes(&mut self, length: usize) -> Result<&[u8], String> {
+    let end = self.index.checked_add(length).filter(|end| *end <= self.data.len());
+
+    match end {
+      Some(end) => {
+        let bytes = &self.data[self.index..end];
+        self.index = end;
+        Ok(bytes)
+      },
+      None => Err(format!("NBT data ends unexpectedly at byte {}, reading {} bytes", self.index, length))
+    }
+  }
+
+  fn read_array<const N: usize>(&mut self) -> Result<[u8; N], String> {
+    Ok(self.read_bytes(N)?.try_into().unwrap())
+  }
+
+  fn read_u8(&mut self) -> Result<u8, String> {
+    Ok(self.read_array::<1>()?[0])
+  }
+
+  fn read_i16(&mut self) -> Result<i16, String> {
+    Ok(i16::from_be_bytes(self.read_array()?))
+  }
+
+  fn read_i32(&mut self) -> Result<i32, String> {
+    Ok(i32::from_be_bytes(self.read_array()?))
+  }
+
+  fn read_i64(&mut self) -> Result<i64, String> {
+    Ok(i64::from_be_bytes(self.read_array()?))
+  }
+
+  fn read_length(&mut self) -> Result<usize, String> {
+    let length = self.read_i32()?;
+    if length < 0 {
+      return Err(format!("negative NBT length {} at byte {}", length, self.index - 4));
+    }
+    Ok(length as usize)
+  }
+
+  fn read_string(&mut self) -> Result<String, String> {
+    let length = u16::from_be_bytes(self.read_array()?) as usize;
+    let bytes = self.read_bytes(length)?;
+
+    Ok(decode_modified_utf8(bytes))
+  }
+
+  fn read_payload(&mut self, tag_id: u8, depth: usize) -> Result<Nbt, String> {
+    if depth > MAX_DEPTH {
+      return Err(format!("NBT data is nested more than {} levels deep", MAX_DEPTH));
+    }
 
-      match id {
-        9 => { // List
-          let (child, next_index) = read_nbt_list(data, index);
-          (Some(child), next_index)
-        },
-        10 => { // Compound
-          let (child, next_index) = read_nbt_compound(data, index);
-          (Some(child), next_index)
-        },
-        _ => { // all other IDs
-          (None, 0)
+    let nbt = match tag_id {
+      TAG_BYTE => Nbt::Byte(self.read_u8()? as i8),
+      TAG_SHORT => Nbt::Short(self.read_i16()?),
+      TAG_INT => Nbt::Int(self.read_i32()?),
+      TAG_LONG => Nbt::Long(self.read_i64()?),
+      TAG_FLOAT => Nbt::Float(f32::from_be_bytes(self.read_array()?)),
+      TAG_DOUBLE => Nbt::Double(f64::from_be_bytes(self.read_array()?)),
+      TAG_BYTE_ARRAY => {
+        let length = self.read_length()?;
+        Nbt::ByteArray(self.read_bytes(length)?.iter().map(|b| *b as i8).collect())
+      },
+      TAG_STRING => Nbt::String(self.read_string()?),
+      TAG_LIST => {
+        let item_tag_id = self.read_u8()?;
+        let length = self.read_length()?;
+
+        if item_tag_id == TAG_END && length > 0 {
+          return Err(format!("NBT list of end tags at byte {}", self.index - 5));
+        }
+
+        // Don't trust the length for the allocation, since it might be corrupt.
+        let mut items = Vec::with_capacity(std::cmp::min(length, 1024));
+        for _ in 0..length {
+          items.push(self.read_payload(item_tag_id, depth + 1)?);
         }
+        Nbt::List(item_tag_id, items)
+      },
+      TAG_COMPOUND => {
+        let mut entries = Vec::new();
+        loop {
+          let entry_tag_id = self.read_u8()?;
+          if entry_tag_id == TAG_END {
+            break;
+          }
+          let name = self.read_string()?;
+          let entry = self.read_payload(entry_tag_id, depth + 1)?;
+          entries.push((name, entry));
+        }
+        Nbt::Compound(entries)
+      },
+      TAG_INT_ARRAY => {
+        let length = self.read_length()?;
+        let bytes = self.read_bytes(length.saturating_mul(4))?;
+        Nbt::IntArray(bytes.chunks_exact(4).map(|b| i32::from_be_bytes(b.try_into().unwrap())).collect())
+      },
+      TAG_LONG_ARRAY => {
+        let length = self.read_length()?;
+        let bytes = self.read_bytes(length.saturating_mul(8))?;
+        Nbt::LongArray(bytes.chunks_exact(8).map(|b| i64::from_be_bytes(b.try_into().unwrap())).collect())
+      },
+      _ => {
+        return Err(format!("unknown NBT tag ID {} at byte {}", tag_id, self.index));
       }
-    }
+    };
+
+    Ok(nbt)
+  }
 }
 
 
-fn get_nbt_data_length_and_offset(id: u8, data: &Vec<u8>, index: usize) -> Option<(usize, usize)> {
-   match id {
-     1 => { // Byte
-       Some((1, 0))
-     },
-     2 => { // Short
-       Some((2, 0))
-     },
-     3 => { // Int
-       Some((4, 0))
-     },
-     4 => { // Long
-       Some((8, 0))
-     },
-     5 => { // Float
-       Some((4, 0))
-     },
-     6 => { // Double
-       Some((8, 0))
-     },
-     7 => { // Byte array
-       Some((read_four_byte_size(data, index), 4))
-     },
-     8 => { // String
-       Some((read_two_byte_size(data, index), 2))
-     },
-     11 => { // Int array
-       Some((4 * read_four_byte_size(data, index), 4))
-     },
-     12 => { // Long array
-       Some((8 * read_four_byte_size(data, index), 4))
-     },
-     _ => { // End (0), List (9), Compound (10) or anything else
-       None
-     }
-   }
+const REPLACEMENT_CHARACTER: u16 = char::REPLACEMENT_CHARACTER as u16;
+
+
+// NBT strings use Java's modified UTF-8, which differs from standard UTF-8 in that
+// the null character is encoded as two bytes, and characters outside the Basic
+// Multilingual Plane are encoded as surrogate pairs of three bytes each.
+// Strings written by other tools may use standard UTF-8 instead, or have invalid bytes,
+// so both encodings are decoded, and anything invalid is replaced with U+FFFD
+// rather than failing the whole chunk.
+pub fn decode_modified_utf8(bytes: &[u8]) -> String {
+  // Fast path for the usual case.
+  if let Ok(s) = std::str::from_utf8(bytes) {
+    return s.to_string();
+  }
+
+  let continuation = |index: usize| -> Option<u32> {
+    bytes.get(index).filter(|b| *b & 0xc0 == 0x80).map(|b| (b & 0x3f) as u32)
+  };
+
+  let mut utf16: Vec<u16> = Vec::with_capacity(bytes.len());
+  let mut i = 0;
+
+  while i < bytes.len() {
+    let b0 = bytes[i] as u32;
+
+    if b0 & 0x80 == 0 {
+      utf16.push(b0 as u16);
+      i += 1;
+    } else if let (0xc0, Some(b1)) = (b0 & 0xe0, continuation(i + 1)) {
+      utf16.push(((b0 & 0x1f) << 6 | b1) as u16);
+      i += 2;
+    } else if let (0xe0, Some(b1), Some(b2)) = (b0 & 0xf0, continuation(i + 1), continuation(i + 2)) {
+      utf16.push(((b0 & 0x0f) << 12 | b1 << 6 | b2) as u16);
+      i += 3;
+    } else if let (0xf0, Some(b1), Some(b2), Some(b3)) =
+      (b0 & 0xf8, continuation(i + 1), continuation(i + 2), continuation(i + 3))
+    {
+      // A character outside the Basic Multilingual Plane in standard UTF-8.
+      match char::from_u32((b0 & 0x07) << 18 | b1 << 12 | b2 << 6 | b3) {
+        Some(c) => utf16.extend_from_slice(c.encode_utf16(&mut [0; 2])),
+        None => utf16.push(REPLACEMENT_CHARACTER),
+      }
+      i += 4;
+    } else {
+      utf16.push(REPLACEMENT_CHARACTER);
+      i += 1;
+    }
+  }
+
+  // Unpaired surrogates are replaced as well.
+  String::from_utf16_lossy(&utf16)
 }
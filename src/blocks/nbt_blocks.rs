@@ -1,6 +1,5 @@
 use std::collections::HashMap;
 use std::cmp;
-use std::str;
 
 use crate::common::{
   ChunkCoords,
@@ -14,86 +13,187 @@ use crate::common::{
 use crate::common::{
   block_name_to_id,
   facing_to_sign_data,
-  shape_to_rail_data,
-  read_i32
+  rotation_to_sign_data,
+  shape_to_rail_data
 };
 
 use crate::blocks::nbt::Nbt;
-use crate::blocks::nbt::read_chunk_nbt;
+use crate::blocks::nbt::read_nbt;
+use crate::blocks::sign_text::extract_sign_texts;
 
 
-pub fn find_chunk_nbt_blocks(chunk_coords: ChunkCoords, chunk_nbt: Vec<u8>) -> Vec<Block> {
+// Data versions at which the chunk format changed.
+// https://minecraft.wiki/w/Data_version
+const DATA_VERSION_FLATTENING: i64 = 1451; // 17w47a, block IDs replaced by palettes of block names
+const DATA_VERSION_UNSPANNED_BLOCK_STATES: i64 = 2529; // 20w17a, palette indices no longer span two longs
+const DATA_VERSION_NO_LEVEL: i64 = 2844; // 21w43a, the Level compound was removed and tags renamed
+
+
+// The names of the chunk tags and the block states encoding, which depend on the data version.
+struct ChunkLayout {
+  data_version: i64,
+  sections: &'static str,
+  palette: &'static str,
+  data: &'static str,
+  block_entities: &'static str,
+  indices_span_data_elements: bool,
+}
+
+
+// Returns the chunk layout, and the compound that contains the sections and block entities.
+fn chunk_layout(chunk_root: &Nbt) -> Result<(ChunkLayout, &Nbt), String> {
+  let data_version = chunk_root.get("DataVersion").and_then(Nbt::as_integer).unwrap_or(0);
+
+  if data_version >= DATA_VERSION_NO_LEVEL {
+    let layout = ChunkLayout {
+      data_version,
+      sections: "sections",
+      palette: "block_states.palette",
+      data: "block_states.data",
+      block_entities: "block_entities",
+      indices_span_data_elements: false,
+    };
+    return Ok((layout, chunk_root));
+  }
+
+  if data_version < DATA_VERSION_FLATTENING {
+    return Err(format!("chunk data version {} is older than 1.13, and must be upgraded", data_version));
+  }
+
+  let level = match chunk_root.get("Level") {
+    Some(level) => level,
+    None => {
+      return Err(format!("chunk with data version {} has no Level tag", data_version));
+    }
+  };
+
+  let layout = ChunkLayout {
+    data_version,
+    sections: "Sections",
+    palette: "Palette",
+    data: "BlockStates",
+    block_entities: "TileEntities",
+    indices_span_data_elements: data_version < DATA_VERSION_UNSPANNED_BLOCK_STATES,
+  };
+  Ok((layout, level))
+}
+
+
+pub fn find_chunk_nbt_blocks(
+  chunk_coords: ChunkCoords,
+  chunk_nbt: Vec<u8>,
+  read_back_text: bool
+) -> Result<Vec<Block>, String> {
   let mut blocks = Vec::new();
 
-  if let Nbt::NbtCompound(chunk_root) = read_chunk_nbt(&chunk_nbt) {
-    let sign_text_map = make_sign_text_map(&chunk_root, chunk_coords);
-
-    if let Some(sections_tag) = chunk_root.get("sections") {
-      if let Nbt::NbtList(sections) = sections_tag {
-        for section_tag in sections {
-          if let Nbt::NbtCompound(section) = section_tag {
-            if let Some(block_states_tag) = section.get("block_states") {
-              if let Nbt::NbtCompound(block_states) = block_states_tag {
-                if let Some(palette_tag) = block_states.get("palette") {
-                  if let Nbt::NbtList(palette) = palette_tag {
-                    if palette.len() > 0 {
-                      if let Some(block_states_data_tag) = block_states.get("data") {
-                        if let Nbt::NbtData(block_states_raw_data) = block_states_data_tag {
-                          if let Some(section_y_tag) = section.get("Y") {
-                            if let Nbt::NbtData(section_y_data) = section_y_tag {
-                              let section_y = section_y_data[0] as i8;
-
-                              let palette_index_width = cmp::max(4,
-                                                                 palette.len()
-                                                                 .next_power_of_two()
-                                                                 .ilog2() as usize);
-                              let indices_per_data_element = (64 as usize).div_euclid(palette_index_width);
-                              
-                              let palette_map = make_palette_map(palette);
-
-                              for block_index in 0..4096 {
-                                let palette_index = get_palette_index(block_index,
-                                                                      block_states_raw_data,
-                                                                      palette_index_width,
-                                                                      indices_per_data_element);
-
-                                if let Some(template_block) = palette_map.get(&palette_index) {
-                                  let block_coords = block_index_to_block_coords(block_index, chunk_coords, section_y);
-
-                                  let block = Block {
-                                    id: template_block.id,
-                                    coords: block_coords,
-                                    rail_data: template_block.rail_data,
-                                    sign_data: template_block.sign_data,
-                                    sign_text: match sign_text_map.get(&block_coords) {
-                                      Some(sign_text) => sign_text.to_string(),
-                                      None => EMPTY
-                                    }
-                                  };
-
-                                  blocks.push(block);
-                                }
-                              }
-                            }
-                          }
-                        }
-                      }
-                    }
-                  }
-                }
-              }
-            }
-          }
-        }
+  let (_, chunk_root) = read_nbt(&chunk_nbt)?;
+
+  let (layout, chunk_level) = chunk_layout(&chunk_root)?;
+
+  let sign_text_map = make_sign_text_map(chunk_level, &layout, chunk_coords, read_back_text);
+
+  let sections = match chunk_level.get(layout.sections).and_then(Nbt::as_list) {
+    Some(sections) => sections,
+    None => {
+      return Ok(blocks);
+    }
+  };
+
+  for section in sections {
+    let palette = match section.get_path(layout.palette).and_then(Nbt::as_list) {
+      Some(palette) if !palette.is_empty() => palette,
+      _ => continue,
+    };
+
+    let section_y = match section.get("Y").and_then(Nbt::as_integer) {
+      Some(section_y) => section_y as i32,
+      None => continue,
+    };
+
+    let palette_map = make_palette_map(palette);
+
+    // Skip the section when none of the palette entries are blocks of interest.
+    if palette_map.is_empty() {
+      continue;
+    }
+
+    let palette_index_width = palette_index_width(palette.len());
+    let indices_per_data_element = 64_usize.div_euclid(palette_index_width);
+
+    let num_data_elements = if layout.indices_span_data_elements {
+      (4096 * palette_index_width).div_ceil(64)
+    } else {
+      4096_usize.div_ceil(indices_per_data_element)
+    };
+
+    // When the data is missing, every block in the section is the first palette entry.
+    // That's always the case for a section with a single palette entry.
+    let zero_block_states_data;
+
+    let block_states_data = match section.get_path(layout.data).and_then(Nbt::as_long_array) {
+      Some(block_states_data) => block_states_data,
+      None => {
+        zero_block_states_data = vec![0; num_data_elements];
+        &zero_block_states_data
+      }
+    };
+
+    if block_states_data.len() < num_data_elements {
+      return Err(format!("block states data of section {} is too short", section_y));
+    }
+
+    for block_index in 0..4096 {
+      let palette_index = if layout.indices_span_data_elements {
+        get_spanning_palette_index(block_index,
+                                   block_states_data,
+                                   palette_index_width)
+      } else {
+        get_palette_index(block_index,
+                          block_states_data,
+                          palette_index_width,
+                          indices_per_data_element)
+      };
+
+      if let Some(template_block) = palette_map.get(&palette_index) {
+        let block_coords = block_index_to_block_coords(block_index, chunk_coords, section_y);
+
+        let (sign_text, back_sign_text) = match sign_text_map.get(&block_coords) {
+          Some((sign_text, back_sign_text)) => (sign_text.to_string(), back_sign_text.to_string()),
+          None => (EMPTY, EMPTY)
+        };
+
+        let block = Block {
+          id: template_block.id,
+          coords: block_coords,
+          rail_data: template_block.rail_data,
+          sign_data: template_block.sign_data,
+          sign_text,
+          back_sign_text,
+          powered: template_block.powered,
+          waterlogged: template_block.waterlogged
+        };
+
+        blocks.push(block);
       }
     }
   }
 
-  blocks
+  Ok(blocks)
+}
+
+
+// The number of bits for each palette index in the block states data is the number of bits
+// needed for the largest palette index, but at least four, e.g. 4 bits for palettes of 1 to 16
+// entries, 5 bits for 17 to 32 entries, and 9 bits for 257 to 512 entries.
+fn palette_index_width(palette_len: usize) -> usize {
+  cmp::max(4,
+           palette_len
+           .next_power_of_two()
+           .ilog2() as usize)
 }
 
 
-fn block_index_to_block_coords(block_index: usize, chunk_coords: ChunkCoords, section_y: i8) -> BlockCoords {
+fn block_index_to_block_coords(block_index: usize, chunk_coords: ChunkCoords, section_y: i32) -> BlockCoords {
   let (chunk_x, chunk_z, realm) = chunk_coords;
 
   let block_y_offset = (block_index as i32).div_euclid(256);
@@ -104,7 +204,7 @@ fn block_index_to_block_coords(block_index: usize, chunk_coords: ChunkCoords, se
   
   (
     block_x_offset + 16 * chunk_x,
-    block_y_offset + 16 * (section_y as i32),
+    block_y_offset + 16 * section_y,
     block_z_offset + 16 * chunk_z,
     realm
   )
@@ -112,122 +212,115 @@ fn block_index_to_block_coords(block_index: usize, chunk_coords: ChunkCoords, se
 
 
 fn get_palette_index(block_index: usize,
-                     block_states_raw_data: &Vec<u8>,
+                     block_states_data: &[i64],
                      palette_index_width: usize,
                      indices_per_data_element: usize) -> usize {
-  let data_element_start = 8 * block_index.div_euclid(indices_per_data_element);
+  let data_element_index = block_index.div_euclid(indices_per_data_element);
   let start_bit = palette_index_width * block_index.rem_euclid(indices_per_data_element);
 
-  let data_element = usize::from_be_bytes( block_states_raw_data[data_element_start..data_element_start+8]
-                                         .try_into()
-                                         .unwrap() );
+  let data_element = block_states_data[data_element_index] as u64;
 
-  data_element
-    .checked_shl((64 - (start_bit + palette_index_width)) as u32)
-    .unwrap()
-    .checked_shr((64 - palette_index_width) as u32)
-    .unwrap()
+  ((data_element >> start_bit) & ((1 << palette_index_width) - 1)) as usize
 }
 
 
-fn make_palette_map(palette: &Vec<Nbt>) -> HashMap<usize, Block> {
+// Before 1.16, palette indices were packed into the data elements without padding,
+// so an index could start in one data element and end in the next.
+fn get_spanning_palette_index(block_index: usize,
+                              block_states_data: &[i64],
+                              palette_index_width: usize) -> usize {
+  let start_bit = palette_index_width * block_index;
+  let data_element_index = start_bit.div_euclid(64);
+  let start_bit_in_element = start_bit.rem_euclid(64);
+
+  let mut palette_index = (block_states_data[data_element_index] as u64) >> start_bit_in_element;
+
+  if start_bit_in_element + palette_index_width > 64 {
+    palette_index |= (block_states_data[data_element_index + 1] as u64) << (64 - start_bit_in_element);
+  }
+
+  (palette_index & ((1 << palette_index_width) - 1)) as usize
+}
+
+
+fn make_palette_map(palette: &[Nbt]) -> HashMap<usize, Block> {
   let mut palette_map = HashMap::new();
 
-  for palette_index in 0..palette.len() {
-    let palette_tag = &palette[palette_index];
-    if let Nbt::NbtCompound(palette) = palette_tag {
-    
-      if let Some(name_tag) = palette.get("Name") {
-        if let Nbt::NbtData(name_data) = name_tag {
-          let name: &str = str::from_utf8(name_data).unwrap();
-
-          if let Some(block_id) = block_name_to_id(name) {
-            if let Some(properties_tag) = palette.get("Properties") {
-              if let Nbt::NbtCompound(properties) = properties_tag {
-
-                let mut rail_data: RailData = RailData::NS;
-                let mut sign_data: SignData = SignData::N;
-
-                if (&block_id).is_rail_id() {
-                  if let Some(shape_tag) = properties.get("shape") {
-                    if let Nbt::NbtData(shape_data) = shape_tag {
-                      let shape: &str = str::from_utf8(shape_data).unwrap();
-                      if let Some(bd) = shape_to_rail_data(shape) {
-                        rail_data = bd;
-                      }
-                    }
-                  }
-                }
-                if (&block_id).is_sign_id() {
-                  if let Some(facing_tag) = properties.get("facing") {
-                    if let Nbt::NbtData(facing_data) = facing_tag {
-                      let facing: &str = str::from_utf8(facing_data).unwrap();
-                      if let Some(bd) = facing_to_sign_data(facing) {
-                        sign_data = bd;
-                      }
-                    }
-                  }
-                }
-
-                let template_block = Block {
-                  id: block_id,
-                  coords: (0, 0, 0, Realm::Overworld),
-                  rail_data: rail_data,
-                  sign_data: sign_data,
-                  sign_text: EMPTY,
-                };
-                palette_map.insert(palette_index, template_block);
-              }
-            }
-          }
-        }
+  for (palette_index, palette_entry) in palette.iter().enumerate() {
+    let block_id = match palette_entry.get("Name").and_then(Nbt::as_str).and_then(block_name_to_id) {
+      Some(block_id) => block_id,
+      None => continue,
+    };
+
+    let properties = match palette_entry.get("Properties") {
+      Some(properties) => properties,
+      None => continue,
+    };
+
+    let mut rail_data: RailData = RailData::NS;
+    let mut sign_data: SignData = SignData::N;
+
+    if block_id.is_rail_id() {
+      if let Some(rd) = properties.get("shape").and_then(Nbt::as_str).and_then(shape_to_rail_data) {
+        rail_data = rd;
       }
     }
+    if block_id.is_sign_id() {
+      if let Some(sd) = properties.get("facing").and_then(Nbt::as_str).and_then(facing_to_sign_data) {
+        sign_data = sd;
+      } else if let Some(sd) = properties.get("rotation").and_then(Nbt::as_str).and_then(rotation_to_sign_data) {
+        sign_data = sd;
+      }
+    }
+
+    let template_block = Block {
+      id: block_id,
+      coords: (0, 0, 0, Realm::Overworld),
+      rail_data,
+      sign_data,
+      sign_text: EMPTY,
+      back_sign_text: EMPTY,
+      powered: properties.get("powered").and_then(Nbt::as_str) == Some("true"),
+      waterlogged: properties.get("waterlogged").and_then(Nbt::as_str) == Some("true"),
+    };
+    palette_map.insert(palette_index, template_block);
   }
   
   palette_map
 }
 
 
-fn make_sign_text_map(chunk_root: &HashMap<String, Nbt>, chunk_coords: ChunkCoords) -> HashMap<BlockCoords, String> {
+fn make_sign_text_map(
+  chunk_level: &Nbt,
+  layout: &ChunkLayout,
+  chunk_coords: ChunkCoords,
+  read_back_text: bool
+) -> HashMap<BlockCoords, (String, String)> {
   let (_, _, realm) = chunk_coords;
 
   let mut sign_text_map = HashMap::new();
 
-  if let Some(block_entities_tag) = chunk_root.get("block_entities") {
-    if let Nbt::NbtList(block_entities) = block_entities_tag {
-      for block_entity_tag in block_entities {
-        if let Nbt::NbtCompound(block_entity) = block_entity_tag {
-          if let Some(block_id_tag) = block_entity.get("id") {
-            if let Nbt::NbtData(block_id_data) = block_id_tag {
-              let block_id: &str = str::from_utf8(block_id_data).unwrap();      
-              if block_id == "minecraft:sign" {
-
-                if let Some(block_x_tag) = block_entity.get("x") {
-                  if let Nbt::NbtData(block_x_data) = block_x_tag {
-                    let block_x = read_i32(block_x_data);
-                    
-                    if let Some(block_y_tag) = block_entity.get("y") {
-                      if let Nbt::NbtData(block_y_data) = block_y_tag {
-                        let block_y = read_i32(block_y_data);
-
-                        if let Some(block_z_tag) = block_entity.get("z") {
-                          if let Nbt::NbtData(block_z_data) = block_z_tag {
-                            let block_z = read_i32(block_z_data);
-
-                            let block_coords = (block_x, block_y, block_z, realm);
-                            sign_text_map.insert(block_coords, extract_sign_text(block_entity));
-                          }
-                        }
-                      }
-                    }
-                  }
-                }
-              }
-            }
-          }
-        }
-      }
+  let block_entities = match chunk_level.get(layout.block_entities).and_then(Nbt::as_list) {
+    Some(block_entities) => block_entities,
+    None => {
+      return sign_text_map;
+    }
+  };
+
+  for block_entity in block_entities {
+    // Hanging signs have their own block entity ID.
+    match block_entity.get("id").and_then(Nbt::as_str) {
+      Some("minecraft:sign") | Some("minecraft:hanging_sign") => {},
+      _ => continue,
+    }
+
+    if let (Some(block_x), Some(block_y), Some(block_z)) = (
+      block_entity.get("x").and_then(Nbt::as_int),
+      block_entity.get("y").and_then(Nbt::as_int),
+      block_entity.get("z").and_then(Nbt::as_int),
+    ) {
+      let block_coords = (block_x, block_y, block_z, realm);
+      sign_text_map.insert(block_coords, extract_sign_texts(block_entity, layout.data_version, read_back_text));
     }
   }
 
@@ -235,53 +328,179 @@ fn make_sign_text_map(chunk_root: &HashMap<String, Nbt>, chunk_coords: ChunkCoor
 }
 
 
-fn extract_sign_text(block_entity: &HashMap<String, Nbt>) -> String {
-  let mut sign_text = EMPTY;
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::common::BlockID;
+  use crate::blocks::nbt::TAG_COMPOUND;
+  use crate::blocks::nbt_writer::write_nbt;
+
+  const RAIL_BLOCK_INDICES: [usize; 3] = [0, 1000, 4095];
 
-  if let Some(front_text_tag) = block_entity.get("front_text") {
-    if let Nbt::NbtCompound(front_text) = front_text_tag {
+  fn palette_entry(name: &str) -> Nbt {
+    Nbt::Compound(vec![
+      ("Name".to_string(), Nbt::String(name.to_string())),
+      ("Properties".to_string(), Nbt::Compound(vec![
+        ("shape".to_string(), Nbt::String("north_south".to_string())),
+      ])),
+    ])
+  }
 
-      if let Some(messages_tag) = front_text.get("messages") {
-        if let Nbt::NbtList(messages) = messages_tag {
+  // A chunk with one section, where the last palette entry is a rail and the others aren't
+  // blocks of interest. The data is left out when block_states_data is None.
+  fn chunk_nbt(palette_len: usize, block_states_data: Option<Vec<i64>>) -> Vec<u8> {
+    let palette = (0..palette_len)
+      .map(|palette_index| palette_entry(if palette_index == palette_len - 1 {"minecraft:rail"} else {"minecraft:stone"}))
+      .collect();
 
-          for message_index in 0..messages.len() {
-            let message_tag = &messages[message_index];
-            if let Nbt::NbtData(message_data) = message_tag {
-              let message = str::from_utf8(message_data).unwrap();
+    let mut block_states = vec![("palette".to_string(), Nbt::List(TAG_COMPOUND, palette))];
+    if let Some(block_states_data) = block_states_data {
+      block_states.push(("data".to_string(), Nbt::LongArray(block_states_data)));
+    }
 
-              let mut next_text = text_from_json(message);
-              next_text = next_text.trim().to_string();
+    let chunk_root = Nbt::Compound(vec![
+      ("DataVersion".to_string(), Nbt::Int(3465)),
+      ("sections".to_string(), Nbt::List(TAG_COMPOUND, vec![
+        Nbt::Compound(vec![
+          ("Y".to_string(), Nbt::Byte(0)),
+          ("block_states".to_string(), Nbt::Compound(block_states)),
+        ]),
+      ])),
+    ]);
+
+    write_nbt("", &chunk_root).unwrap()
+  }
+
+  // Packs palette indices the way 1.16 and later do, without spanning data elements.
+  fn pack_palette_indices(palette_indices: &[usize], palette_index_width: usize) -> Vec<i64> {
+    let indices_per_data_element = 64 / palette_index_width;
+
+    palette_indices
+      .chunks(indices_per_data_element)
+      .map(|element_indices| {
+        element_indices
+          .iter()
+          .enumerate()
+          .fold(0u64, |data_element, (i, palette_index)| data_element | (*palette_index as u64) << (i * palette_index_width))
+          as i64
+      })
+      .collect()
+  }
+
+  fn rail_block_indices(chunk_nbt: Vec<u8>) -> Vec<usize> {
+    let mut block_indices: Vec<usize> = find_chunk_nbt_blocks((0, 0, Realm::Overworld), chunk_nbt, false)
+      .unwrap()
+      .iter()
+      .map(|block| {
+        let (x, y, z, _) = block.coords;
+        (256 * y + 16 * z + x) as usize
+      })
+      .collect();
+    block_indices.sort();
+    block_indices
+  }
+
+  #[test]
+  fn palette_index_widths() {
+    assert_eq!(palette_index_width(1), 4);
+    assert_eq!(palette_index_width(16), 4);
+    assert_eq!(palette_index_width(17), 5);
+    assert_eq!(palette_index_width(256), 8);
+    assert_eq!(palette_index_width(257), 9);
+    assert_eq!(palette_index_width(512), 9);
+    assert_eq!(palette_index_width(513), 10);
+  }
 
-              if sign_text != "" && next_text != "" {
-                sign_text.push_str(" ");
-              }
-              sign_text.push_str(&next_text);
-            }
-          }
-        }
+  #[test]
+  fn decodes_palettes_of_each_width() {
+    for palette_len in [2, 16, 17, 256, 257, 300] {
+      let mut palette_indices = vec![0; 4096];
+      for block_index in RAIL_BLOCK_INDICES {
+        palette_indices[block_index] = palette_len - 1;
       }
+
+      let block_states_data = pack_palette_indices(&palette_indices, palette_index_width(palette_len));
+
+      assert_eq!(
+        rail_block_indices(chunk_nbt(palette_len, Some(block_states_data))),
+        RAIL_BLOCK_INDICES.to_vec(),
+        "palette of {} entries", palette_len
+      );
     }
   }
 
-  sign_text
-}
+  #[test]
+  fn section_without_data_is_all_first_palette_entry() {
+    // Minecraft leaves out the data when the palette has a single entry.
+    assert_eq!(rail_block_indices(chunk_nbt(1, None)), (0..4096).collect::<Vec<usize>>());
 
+    // The rail is the second palette entry, so there are no rails.
+    assert_eq!(rail_block_indices(chunk_nbt(2, None)), Vec::<usize>::new());
+  }
 
-fn text_from_json(json: &str) -> String {
-  let val: serde_json::Value = serde_json::from_str(json).unwrap();
+  #[test]
+  fn decodes_palette_indices_spanning_data_elements() {
+    // With 5 bit palette indices, block 12 starts at bit 60, so its lowest 4 bits are
+    // the top of the first data element and its highest bit is the bottom of the second.
+    let block_states_data = [0x6000_0000_0000_0000_u64 as i64, 0x1];
+    assert_eq!(get_spanning_palette_index(11, &block_states_data, 5), 0);
+    assert_eq!(get_spanning_palette_index(12, &block_states_data, 5), 0b10110);
+    assert_eq!(get_spanning_palette_index(13, &block_states_data, 5), 0);
+
+    // A 1.15 chunk, with the Level compound and a 5 bit palette, where the rails at
+    // blocks 12 and 25 span data elements, and the rail at block 1000 doesn't.
+    let palette_len = 17;
+    let rail_block_indices_spanning = [12, 25, 1000];
+
+    let mut bits: Vec<bool> = Vec::new();
+    for block_index in 0..4096 {
+      let palette_index = if rail_block_indices_spanning.contains(&block_index) {palette_len - 1} else {block_index % 16};
+      bits.extend((0..5).map(|bit| (palette_index >> bit) & 1 == 1));
+    }
+    let block_states_data: Vec<i64> = bits
+      .chunks(64)
+      .map(|element_bits| element_bits.iter().rev().fold(0u64, |data_element, bit| data_element << 1 | *bit as u64) as i64)
+      .collect();
+
+    let palette = (0..palette_len)
+      .map(|palette_index| palette_entry(if palette_index == palette_len - 1 {"minecraft:rail"} else {"minecraft:stone"}))
+      .collect();
+
+    let chunk_root = Nbt::Compound(vec![
+      ("DataVersion".to_string(), Nbt::Int(2230)),
+      ("Level".to_string(), Nbt::Compound(vec![
+        ("Sections".to_string(), Nbt::List(TAG_COMPOUND, vec![
+          Nbt::Compound(vec![
+            ("Y".to_string(), Nbt::Byte(0)),
+            ("Palette".to_string(), Nbt::List(TAG_COMPOUND, palette)),
+            ("BlockStates".to_string(), Nbt::LongArray(block_states_data)),
+          ]),
+        ])),
+      ])),
+    ]);
+
+    assert_eq!(rail_block_indices(write_nbt("", &chunk_root).unwrap()), rail_block_indices_spanning.to_vec());
+  }
 
-  match val {
-    serde_json::Value::String(text) => text,
-    serde_json::Value::Object(object) => {
-      match object.get("text") {
-        Some(text_val) => match text_val {
-          serde_json::Value::String(text) => text.to_string(),
-          _ => EMPTY
-        }
-        _ => EMPTY
-      }
-    },
-      _ => EMPTY
+  #[test]
+  fn only_vanilla_blocks_are_in_palette_map() {
+    let palette = [
+      palette_entry("othermod:rail"),
+      palette_entry("minecraft:rail"),
+      palette_entry("rail"),
+      palette_entry("othermod:oak_sign"),
+      palette_entry("minecraft:cherry_hanging_sign"),
+      palette_entry("minecraft:stone"),
+    ];
+
+    let palette_map = make_palette_map(&palette);
+
+    let mut palette_indices: Vec<usize> = palette_map.keys().copied().collect();
+    palette_indices.sort();
+    assert_eq!(palette_indices, vec![1, 2, 4]);
+
+    assert_eq!(palette_map[&1].id, BlockID::UnpoweredRail);
+    assert_eq!(palette_map[&4].id, BlockID::HangingSign);
+    assert_eq!(palette_map[&4].id as u32, 69);
   }
 }
-
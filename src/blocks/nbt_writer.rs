@@ -0,0 +1,363 @@
+use std::io::Write;
+
+use flate2::Compression;
+use flate2::write::{GzEncoder, ZlibEncoder};
+
+use crate::blocks::nbt::{Nbt, TAG_END};
+use crate::blocks::chunk_compression::{COMPRESSION_GZIP, COMPRESSION_ZLIB, COMPRESSION_NONE};
+
+
+// Writes an NBT root tag uncompressed. Reading the result with read_nbt
+// returns the same name and tag. Writing a tag that was read with read_nbt
+// produces the same bytes that were read, as long as its strings were in the
+// modified UTF-8 that Minecraft writes, since other encodings that the reader
+// accepts are written back as modified UTF-8. Returns an error if a string
+// is too long to be written as NBT.
+pub fn write_nbt(name: &str, root: &Nbt) -> Result<Vec<u8>, String> {
+  let mut data = Vec::new();
+
+  data.push(root.tag_id());
+  write_string(&mut data, name)?;
+  write_payload(&mut data, root)?;
+
+  Ok(data)
+}
+
+
+// Writes an NBT root tag compressed with one of the compression types used in
+// region files. Standalone NBT files such as level.dat and structure files are gzipped.
+pub fn write_compressed_nbt(name: &str, root: &Nbt, compression_type: u8) -> Result<Vec<u8>, String> {
+  let data = write_nbt(name, root)?;
+
+  match compression_type {
+    COMPRESSION_GZIP => {
+      let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+      encoder.write_all(&data)
+        .and_then(|_| encoder.finish())
+        .map_err(|err| format!("gzip error: {}", err))
+    },
+    COMPRESSION_ZLIB => {
+      let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+      encoder.write_all(&data)
+        .and_then(|_| encoder.finish())
+        .map_err(|err| format!("zlib error: {}", err))
+    },
+    COMPRESSION_NONE => {
+      Ok(data)
+    },
+    _ => {
+      Err(format!("can't write NBT with compression type {}", compression_type))
+    }
+  }
+}
+
+
+fn write_length(data: &mut Vec<u8>, length: usize) {
+  data.extend_from_slice(&(length as i32).to_be_bytes());
+}
+
+
+// The length of an NBT string is an unsigned short, so longer strings can't be written.
+fn write_string(data: &mut Vec<u8>, s: &str) -> Result<(), String> {
+  let bytes = encode_modified_utf8(s);
+
+  let length = match u16::try_from(bytes.len()) {
+    Ok(length) => length,
+    Err(_) => {
+      return Err(format!("NBT string of {} bytes is longer than the maximum of {} bytes", bytes.len(), u16::MAX));
+    }
+  };
+
+  data.extend_from_slice(&length.to_be_bytes());
+  data.extend_from_slice(&bytes);
+
+  Ok(())
+}
+
+
+fn write_payload(data: &mut Vec<u8>, nbt: &Nbt) -> Result<(), String> {
+  match nbt {
+    Nbt::Byte(v) => data.push(*v as u8),
+    Nbt::Short(v) => data.extend_from_slice(&v.to_be_bytes()),
+    Nbt::Int(v) => data.extend_from_slice(&v.to_be_bytes()),
+    Nbt::Long(v) => data.extend_from_slice(&v.to_be_bytes()),
+    Nbt::Float(v) => data.extend_from_slice(&v.to_be_bytes()),
+    Nbt::Double(v) => data.extend_from_slice(&v.to_be_bytes()),
+    Nbt::ByteArray(items) => {
+      write_length(data, items.len());
+      data.extend(items.iter().map(|v| *v as u8));
+    },
+    Nbt::String(v) => write_string(data, v)?,
+    Nbt::List(item_tag_id, items) => {
+      // A non-empty list's tag ID always comes from its items,
+      // in case the items were changed after the list was read.
+      // All of the items in a list must have the same tag ID.
+      let list_tag_id = items.first().map_or(*item_tag_id, Nbt::tag_id);
+      data.push(list_tag_id);
+      write_length(data, items.len());
+      for (item_index, item) in items.iter().enumerate() {
+        if item.tag_id() != list_tag_id {
+          return Err(format!("NBT list item {} has tag ID {} instead of the list's tag ID {}", item_index, item.tag_id(), list_tag_id));
+        }
+        write_payload(data, item)?;
+      }
+    },
+    Nbt::Compound(entries) => {
+      for (name, entry) in entries {
+        data.push(entry.tag_id());
+        write_string(data, name)?;
+        write_payload(data, entry)?;
+      }
+      data.push(TAG_END);
+    },
+    Nbt::IntArray(items) => {
+      write_length(data, items.len());
+      for v in items {
+        data.extend_from_slice(&v.to_be_bytes());
+      }
+    },
+    Nbt::LongArray(items) => {
+      write_length(data, items.len());
+      for v in items {
+        data.extend_from_slice(&v.to_be_bytes());
+      }
+    },
+  }
+
+  Ok(())
+}
+
+
+// The inverse of decode_modified_utf8 in the NBT reader for strings in modified UTF-8.
+pub fn encode_modified_utf8(s: &str) -> Vec<u8> {
+  let mut bytes = Vec::with_capacity(s.len());
+
+  for unit in s.encode_utf16() {
+    match unit {
+      0x0001..=0x007f => {
+        bytes.push(unit as u8);
+      },
+      0x0000 | 0x0080..=0x07ff => {
+        bytes.push(0xc0 | (unit >> 6) as u8);
+        bytes.push(0x80 | (unit & 0x3f) as u8);
+      },
+      _ => {
+        bytes.push(0xe0 | (unit >> 12) as u8);
+        bytes.push(0x80 | ((unit >> 6) & 0x3f) as u8);
+        bytes.push(0x80 | (unit & 0x3f) as u8);
+      }
+    }
+  }
+
+  bytes
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::blocks::nbt::*;
+  use crate::blocks::chunk_compression::{COMPRESSION_LZ4, decompress_chunk_data};
+
+  // The NBT in these tests is put together byte by byte, so that it doesn't depend on the writer.
+
+  fn raw_string(bytes: &[u8]) -> Vec<u8> {
+    let mut raw = (bytes.len() as u16).to_be_bytes().to_vec();
+    raw.extend_from_slice(bytes);
+    raw
+  }
+
+  fn raw_entry(tag_id: u8, name: &str, payload: Vec<u8>) -> Vec<u8> {
+    let mut raw = vec![tag_id];
+    raw.extend(raw_string(name.as_bytes()));
+    raw.extend(payload);
+    raw
+  }
+
+  fn raw_compound(entries: Vec<Vec<u8>>) -> Vec<u8> {
+    let mut raw = entries.concat();
+    raw.push(TAG_END);
+    raw
+  }
+
+  fn raw_list(item_tag_id: u8, items: Vec<Vec<u8>>) -> Vec<u8> {
+    let mut raw = vec![item_tag_id];
+    raw.extend((items.len() as i32).to_be_bytes());
+    raw.extend(items.concat());
+    raw
+  }
+
+  fn raw_long_array(items: &[i64]) -> Vec<u8> {
+    let mut raw = (items.len() as i32).to_be_bytes().to_vec();
+    for v in items {
+      raw.extend(v.to_be_bytes());
+    }
+    raw
+  }
+
+  // A sign's text, with messages in modified UTF-8.
+  fn raw_sign_text(messages: &[&[u8]]) -> Vec<u8> {
+    raw_compound(vec![
+      raw_entry(TAG_LIST, "messages", raw_list(TAG_STRING, messages.iter().map(|message| raw_string(message)).collect())),
+      raw_entry(TAG_STRING, "color", raw_string(b"black")),
+      raw_entry(TAG_BYTE, "has_glowing_text", vec![0]),
+    ])
+  }
+
+  // A chunk laid out the way Minecraft 1.20 writes it, with a rail section, a sign with
+  // a supplementary character and a null character in its text, and every tag type.
+  fn raw_chunk() -> Vec<u8> {
+    let section = raw_compound(vec![
+      raw_entry(TAG_BYTE, "Y", vec![0xfc]),
+      raw_entry(TAG_COMPOUND, "block_states", raw_compound(vec![
+        raw_entry(TAG_LIST, "palette", raw_list(TAG_COMPOUND, vec![
+          raw_compound(vec![raw_entry(TAG_STRING, "Name", raw_string(b"minecraft:air"))]),
+          raw_compound(vec![
+            raw_entry(TAG_STRING, "Name", raw_string(b"minecraft:rail")),
+            raw_entry(TAG_COMPOUND, "Properties", raw_compound(vec![
+              raw_entry(TAG_STRING, "shape", raw_string(b"north_south")),
+              raw_entry(TAG_STRING, "waterlogged", raw_string(b"false")),
+            ])),
+          ]),
+        ])),
+        raw_entry(TAG_LONG_ARRAY, "data", raw_long_array(&[0x1111_0000_0000_0001; 64])),
+      ])),
+      raw_entry(TAG_COMPOUND, "biomes", raw_compound(vec![
+        raw_entry(TAG_LIST, "palette", raw_list(TAG_STRING, vec![raw_string(b"minecraft:plains")])),
+      ])),
+      raw_entry(TAG_BYTE_ARRAY, "SkyLight", [&2048i32.to_be_bytes()[..], &[0xf0; 2048]].concat()),
+    ]);
+
+    let sign = raw_compound(vec![
+      raw_entry(TAG_STRING, "id", raw_string(b"minecraft:oak_sign")),
+      raw_entry(TAG_INT, "x", 3i32.to_be_bytes().to_vec()),
+      raw_entry(TAG_INT, "y", (-60i32).to_be_bytes().to_vec()),
+      raw_entry(TAG_INT, "z", 7i32.to_be_bytes().to_vec()),
+      raw_entry(TAG_BYTE, "is_waxed", vec![0]),
+      // U+1F689 as a surrogate pair, and "é".
+      raw_entry(TAG_COMPOUND, "front_text", raw_sign_text(&[
+        b"\"Zulu\"",
+        b"\"\xed\xa0\xbd\xed\xba\x89 caf\xc3\xa9\"",
+        b"\"\"",
+        b"\"\"",
+      ])),
+      // A null character.
+      raw_entry(TAG_COMPOUND, "back_text", raw_sign_text(&[
+        b"\"a\xc0\x80b\"",
+        b"\"\"",
+        b"\"\"",
+        b"\"\"",
+      ])),
+    ]);
+
+    raw_entry(TAG_COMPOUND, "", raw_compound(vec![
+      raw_entry(TAG_INT, "DataVersion", 3465i32.to_be_bytes().to_vec()),
+      raw_entry(TAG_INT, "xPos", 0i32.to_be_bytes().to_vec()),
+      raw_entry(TAG_INT, "yPos", (-4i32).to_be_bytes().to_vec()),
+      raw_entry(TAG_INT, "zPos", 0i32.to_be_bytes().to_vec()),
+      raw_entry(TAG_STRING, "Status", raw_string(b"minecraft:full")),
+      raw_entry(TAG_LONG, "LastUpdate", 123456789i64.to_be_bytes().to_vec()),
+      raw_entry(TAG_LONG, "InhabitedTime", 0i64.to_be_bytes().to_vec()),
+      raw_entry(TAG_SHORT, "Version", 19i16.to_be_bytes().to_vec()),
+      raw_entry(TAG_FLOAT, "Scale", 0.5f32.to_be_bytes().to_vec()),
+      raw_entry(TAG_DOUBLE, "Gametime", 1.25f64.to_be_bytes().to_vec()),
+      raw_entry(TAG_LIST, "sections", raw_list(TAG_COMPOUND, vec![section])),
+      raw_entry(TAG_LIST, "block_entities", raw_list(TAG_COMPOUND, vec![sign])),
+      raw_entry(TAG_LIST, "entities", raw_list(TAG_END, vec![])),
+      raw_entry(TAG_LIST, "block_ticks", raw_list(TAG_COMPOUND, vec![])),
+      raw_entry(TAG_INT_ARRAY, "Offsets", [&2i32.to_be_bytes()[..], &1i32.to_be_bytes(), &(-1i32).to_be_bytes()].concat()),
+      raw_entry(TAG_COMPOUND, "Heightmaps", raw_compound(vec![
+        raw_entry(TAG_LONG_ARRAY, "MOTION_BLOCKING", raw_long_array(&[0x0100_4020_1008_0402; 37])),
+      ])),
+    ]))
+  }
+
+  #[test]
+  fn chunk_round_trip_is_byte_identical() {
+    let chunk = raw_chunk();
+
+    let (name, root) = read_nbt(&chunk).unwrap();
+
+    assert_eq!(
+      root.get_path("block_entities.0.front_text.messages.1").and_then(Nbt::as_str),
+      Some("\"\u{1f689} café\"")
+    );
+    assert_eq!(
+      root.get_path("block_entities.0.back_text.messages.0").and_then(Nbt::as_str),
+      Some("\"a\0b\"")
+    );
+
+    assert_eq!(write_nbt(&name, &root).unwrap(), chunk);
+  }
+
+  #[test]
+  fn supplementary_and_null_characters_round_trip() {
+    let s = "\u{1f689}\0\u{e9}";
+    let encoded = encode_modified_utf8(s);
+
+    assert_eq!(encoded, b"\xed\xa0\xbd\xed\xba\x89\xc0\x80\xc3\xa9");
+    assert_eq!(decode_modified_utf8(&encoded), s);
+
+    let (name, root) = read_nbt(&raw_entry(TAG_STRING, "", raw_string(&encoded))).unwrap();
+    assert_eq!(write_nbt(&name, &root).unwrap(), raw_entry(TAG_STRING, "", raw_string(&encoded)));
+  }
+
+  #[test]
+  fn other_encodings_are_read_and_written_as_modified_utf8() {
+    // Standard UTF-8 written by other tools, which isn't modified UTF-8.
+    assert_eq!(decode_modified_utf8("\u{1f689}".as_bytes()), "\u{1f689}");
+    assert_eq!(decode_modified_utf8(b"a\0b"), "a\0b");
+    assert_eq!(decode_modified_utf8(b"\xed\xa0\xbd\xed\xba\x89 \xf0\x9f\x9a\x89"), "\u{1f689} \u{1f689}");
+    // An overlong encoding of "A".
+    assert_eq!(decode_modified_utf8(b"\xc1\x81\xc3\xa9"), "A\u{e9}");
+    // Invalid bytes, a truncated character and an unpaired surrogate are replaced.
+    assert_eq!(decode_modified_utf8(b"a\xffb\xc3"), "a\u{fffd}b\u{fffd}");
+    assert_eq!(decode_modified_utf8(b"\xed\xa0\xbd!\xc0\x80"), "\u{fffd}!\0");
+
+    // The string is still read, and is written back in modified UTF-8.
+    let standard = raw_entry(TAG_STRING, "", raw_string("\u{1f689}".as_bytes()));
+    let (name, root) = read_nbt(&standard).unwrap();
+    assert_eq!(root.as_str(), Some("\u{1f689}"));
+    assert_eq!(write_nbt(&name, &root).unwrap(), raw_entry(TAG_STRING, "", raw_string(b"\xed\xa0\xbd\xed\xba\x89")));
+  }
+
+  #[test]
+  fn string_too_long_is_an_error() {
+    let longest = Nbt::String("a".repeat(u16::MAX as usize));
+    assert!(write_nbt("", &longest).is_ok());
+
+    let too_long = Nbt::String("a".repeat(u16::MAX as usize + 1));
+    assert!(write_nbt("", &too_long).is_err());
+
+    // Each of these characters takes three bytes in modified UTF-8.
+    let too_long_encoded = Nbt::Compound(vec![("sign".to_string(), Nbt::String("\u{2192}".repeat(30000)))]);
+    assert!(write_nbt("", &too_long_encoded).is_err());
+  }
+
+  #[test]
+  fn mixed_list_is_an_error() {
+    let ints = Nbt::List(TAG_INT, vec![Nbt::Int(1), Nbt::Int(2)]);
+    assert_eq!(write_nbt("", &ints).unwrap(), raw_entry(TAG_LIST, "", raw_list(TAG_INT, vec![
+      1i32.to_be_bytes().to_vec(),
+      2i32.to_be_bytes().to_vec(),
+    ])));
+
+    let mixed = Nbt::Compound(vec![
+      ("list".to_string(), Nbt::List(TAG_INT, vec![Nbt::Int(1), Nbt::String("2".to_string())])),
+    ]);
+    assert!(write_nbt("", &mixed).is_err());
+  }
+
+  #[test]
+  fn compressed_chunk_round_trip() {
+    let chunk = raw_chunk();
+    let (name, root) = read_nbt(&chunk).unwrap();
+
+    for compression_type in [COMPRESSION_GZIP, COMPRESSION_ZLIB, COMPRESSION_NONE] {
+      let compressed_data = write_compressed_nbt(&name, &root, compression_type).unwrap();
+      assert_eq!(decompress_chunk_data(compression_type, &compressed_data), Ok(chunk.clone()));
+    }
+
+    assert!(write_compressed_nbt(&name, &root, COMPRESSION_LZ4).is_err());
+  }
+}
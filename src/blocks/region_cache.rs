@@ -1,52 +1,200 @@
-use std::io::Read;
-use std::collections::HashMap;
+use std::fs::File;
+use std::io::{Read, ErrorKind};
+use std::ops::Deref;
+use std::collections::{BTreeMap, HashMap};
 
-use crate::common::{RegionCoords, Region, Realm};
-use crate::common::{open_file};
+use memmap2::Mmap;
 
+use crate::common::{RegionCoords, Realm};
 
-const MAX_CACHED_REGIONS: usize = 10;
 
+pub const DEFAULT_MAX_CACHED_REGIONS: usize = 10;
 
-fn read_region_data(region_coords: RegionCoords, world_dir: &String) -> Option<Vec<u8>> {
-  let (x, z, realm) = region_coords;
 
+// The capacity of the region cache, as either a number of regions or a number of bytes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RegionCacheCapacity {
+  Regions(usize),
+  Bytes(usize),
+}
+
+impl RegionCacheCapacity {
+  // Parses a number of regions, e.g. "20", or a number of bytes with a suffix
+  // of B, K, KB, M, MB, G or GB, e.g. "512M".
+  pub fn capacity_from_str(capacity_str: &str) -> RegionCacheCapacity {
+    match RegionCacheCapacity::parse_capacity(capacity_str) {
+      Some(capacity) => capacity,
+      None => {
+        exit!("Error parsing region cache capacity {:?}, expected a number of regions, e.g. 20, or a number of bytes with a suffix of B, K, M or G, e.g. 512M",
+              capacity_str);
+      }
+    }
+  }
+
+  fn parse_capacity(capacity_str: &str) -> Option<RegionCacheCapacity> {
+    let upper_capacity_str = capacity_str.to_uppercase();
+
+    // Only one suffix is stripped, so e.g. "1KBB" isn't a capacity.
+    let (number_str, multiplier) = [("KB", 1 << 10), ("MB", 1 << 20), ("GB", 1 << 30), ("K", 1 << 10), ("M", 1 << 20), ("G", 1 << 30), ("B", 1)]
+      .iter()
+      .find_map(|(suffix, multiplier)| upper_capacity_str.strip_suffix(suffix).map(|number_str| (number_str, *multiplier)))
+      .unwrap_or((&upper_capacity_str, 0));
+
+    match number_str.parse::<usize>() {
+      Ok(n) if n > 0 => {
+        if multiplier == 0 {
+          Some(RegionCacheCapacity::Regions(n))
+        } else {
+          n.checked_mul(multiplier).map(RegionCacheCapacity::Bytes)
+        }
+      },
+      _ => None,
+    }
+  }
+}
+
+
+// Region data is either read into memory, or memory-mapped.
+pub enum RegionData {
+  Read(Vec<u8>),
+  Mapped(Mmap),
+}
+
+impl Deref for RegionData {
+  type Target = [u8];
+
+  fn deref(&self) -> &[u8] {
+    match self {
+      RegionData::Read(data) => data,
+      RegionData::Mapped(mmap) => mmap,
+    }
+  }
+}
+
+
+pub struct Region {
+  pub data: RegionData,
+  // Set when the region file couldn't be read, so that each chunk in it is reported as a warning.
+  pub read_error: Option<String>,
+  pub last_chunk_num: u32
+}
+
+
+// A least recently used cache of regions. Recency is tracked by the number of the chunk
+// that last used each region, since chunk numbers increase as chunks are processed.
+pub struct RegionCache {
+  regions: HashMap<RegionCoords, Region>,
+  regions_by_last_chunk_num: BTreeMap<u32, RegionCoords>,
+  capacity: RegionCacheCapacity,
+  use_mmap: bool,
+  num_bytes: usize,
+  hits: usize,
+  misses: usize,
+  evictions: usize,
+}
+
+impl RegionCache {
+  pub fn new(capacity: RegionCacheCapacity, use_mmap: bool) -> RegionCache {
+    RegionCache {
+      regions: HashMap::new(),
+      regions_by_last_chunk_num: BTreeMap::new(),
+      capacity,
+      use_mmap,
+      num_bytes: 0,
+      hits: 0,
+      misses: 0,
+      evictions: 0,
+    }
+  }
+
+  pub fn get(&self, region_coords: &RegionCoords) -> Option<&Region> {
+    self.regions.get(region_coords)
+  }
+
+  fn is_over_capacity(&self) -> bool {
+    match self.capacity {
+      RegionCacheCapacity::Regions(max_regions) => self.regions.len() > max_regions,
+      RegionCacheCapacity::Bytes(max_bytes) => self.num_bytes > max_bytes,
+    }
+  }
+
+  pub fn stats_string(&self) -> String {
+    format!("Region cache: {} hits, {} misses, {} evictions",
+            self.hits,
+            self.misses,
+            self.evictions)
+  }
+}
+
+
+pub fn region_dir_path(realm: Realm, world_dir: &String) -> String {
   let realm_dir = match realm {
     Realm::Overworld => "",
     Realm::Nether => "/DIM-1",
     Realm::End => "/DIM1",
   };
-  
-  let region_path = format!("{}{}/region/r.{}.{}.mca", world_dir, realm_dir, x, z);
-
-  println!("{:?}", region_coords);
-  
-  let mut file = open_file(&region_path);
-  let mut data = Vec::new();
 
-  file.read_to_end(&mut data).ok();
-  
-  Some(data)
+  format!("{}{}/region", world_dir, realm_dir)
 }
 
 
-fn maybe_shrink_region_cache(region_cache: &mut HashMap<RegionCoords, Region>) {
-  if region_cache.len() <= MAX_CACHED_REGIONS {
-    return
-  }
+// Returns None when the region file doesn't exist, which happens when rails
+// run to the edge of the generated part of the world, or an error message
+// when the region file can't be read completely.
+fn read_region_data(
+  region_coords: RegionCoords,
+  world_dir: &String,
+  use_mmap: bool
+) -> Result<Option<RegionData>, String> {
+  let (x, z, realm) = region_coords;
 
-  let mut oldest_chunk_num = u32::MAX;
-  let mut oldest_region_coords: Option<RegionCoords> = None;
+  let region_path = format!("{}/r.{}.{}.mca", region_dir_path(realm, world_dir), x, z);
 
-  for (region_coords, region) in region_cache.iter() {
-    if region.last_chunk_num < oldest_chunk_num {
-      oldest_chunk_num = region.last_chunk_num;
-      oldest_region_coords = Some(region_coords.clone());
+  let mut file = match File::open(&region_path) {
+    Ok(file) => file,
+    Err(err) if err.kind() == ErrorKind::NotFound => {
+      println!("{:?} (missing)", region_coords);
+      return Ok(None);
+    },
+    Err(err) => {
+      exit!("Can't open file {:?}: {}", region_path, err);
+    }
+  };
+
+  println!("{:?}", region_coords);
+
+  if use_mmap {
+    // Safety: the mapping is only valid as long as the region file isn't truncated
+    // while autorail is running, so the world shouldn't be open in a running server.
+    match unsafe { Mmap::map(&file) } {
+      Ok(mmap) => {
+        return Ok(Some(RegionData::Mapped(mmap)));
+      },
+      Err(err) => {
+        exit!("Can't memory-map file {:?}: {}", region_path, err);
+      }
     }
   }
 
-  if let Some(region_coords) = oldest_region_coords {
-    region_cache.remove(&region_coords);
+  let mut data = Vec::new();
+
+  if let Err(err) = file.read_to_end(&mut data) {
+    return Err(format!("can't read region file {:?}: {}", region_path, err));
+  }
+
+  Ok(Some(RegionData::Read(data)))
+}
+
+
+fn maybe_shrink_region_cache(region_cache: &mut RegionCache) {
+  // The most recently used region is never evicted, even if it's larger than the capacity.
+  while region_cache.is_over_capacity() && region_cache.regions.len() > 1 {
+    if let Some((_, region_coords)) = region_cache.regions_by_last_chunk_num.pop_first() {
+      if let Some(region) = region_cache.regions.remove(&region_coords) {
+        region_cache.num_bytes -= region.data.len();
+        region_cache.evictions += 1;
+      }
+    }
   }
 }
 
@@ -55,25 +203,56 @@ pub fn update_region_cache(
   region_coords: RegionCoords,
   chunk_num: u32,
   world_dir: &String,
-  region_cache: &mut HashMap<RegionCoords, Region>
+  region_cache: &mut RegionCache
 ) {
-  if let Some(region) = region_cache.get_mut(&region_coords) {
+  if let Some(region) = region_cache.regions.get_mut(&region_coords) {
+    region_cache.regions_by_last_chunk_num.remove(&region.last_chunk_num);
+    region_cache.regions_by_last_chunk_num.insert(chunk_num, region_coords);
     region.last_chunk_num = chunk_num;
+    region_cache.hits += 1;
     return;
   }
 
-  let data = read_region_data(region_coords, world_dir);
-  if data == None {
-    return;
-  }
+  region_cache.misses += 1;
+
+  // A missing or unreadable region is cached with no data, so that we don't keep looking for it.
+  let (data, read_error) = match read_region_data(region_coords, world_dir, region_cache.use_mmap) {
+    Ok(data_option) => (data_option.unwrap_or(RegionData::Read(vec![])), None),
+    Err(err) => (RegionData::Read(vec![]), Some(err)),
+  };
+
+  region_cache.num_bytes += data.len();
 
   let region = Region {
-    coords: region_coords,
-    data: data.unwrap(),
+    data,
+    read_error,
     last_chunk_num: chunk_num
   };
 
-  region_cache.insert(region_coords, region);
+  region_cache.regions.insert(region_coords, region);
+  region_cache.regions_by_last_chunk_num.insert(chunk_num, region_coords);
 
   maybe_shrink_region_cache(region_cache);
 }
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn parses_capacities() {
+    assert_eq!(RegionCacheCapacity::parse_capacity("20"), Some(RegionCacheCapacity::Regions(20)));
+    assert_eq!(RegionCacheCapacity::parse_capacity("20B"), Some(RegionCacheCapacity::Bytes(20)));
+    assert_eq!(RegionCacheCapacity::parse_capacity("512m"), Some(RegionCacheCapacity::Bytes(512 << 20)));
+    assert_eq!(RegionCacheCapacity::parse_capacity("1KB"), Some(RegionCacheCapacity::Bytes(1 << 10)));
+    assert_eq!(RegionCacheCapacity::parse_capacity("2GB"), Some(RegionCacheCapacity::Bytes(2 << 30)));
+  }
+
+  #[test]
+  fn rejects_invalid_capacities() {
+    for capacity_str in ["1KBB", "1MBBB", "B", "0", "0K", "K20", "-1", "99999999999G"] {
+      assert_eq!(RegionCacheCapacity::parse_capacity(capacity_str), None, "{:?}", capacity_str);
+    }
+  }
+}
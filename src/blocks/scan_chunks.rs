@@ -0,0 +1,84 @@
+use std::fs::read_dir;
+use std::io::ErrorKind;
+
+use crate::common::{
+  ChunkCoords,
+  Realm
+};
+use crate::common::{
+  coord_from_str,
+  realm_from_str,
+  block_coords_to_chunk_coords
+};
+
+use crate::blocks::region_cache::region_dir_path;
+
+
+// Returns the coordinates of every chunk that overlaps an area given in block coordinates
+// as "x1,z1,x2,z2", optionally followed by a realm, e.g. "-500,-500,500,500,nether".
+pub fn area_chunk_coords(area_str: &str) -> Vec<ChunkCoords> {
+  let split: Vec<&str> = area_str.split(',').map(str::trim).collect();
+
+  let (x1_str, z1_str, x2_str, z2_str, realm) = match split[..] {
+    [x1_str, z1_str, x2_str, z2_str] => (x1_str, z1_str, x2_str, z2_str, Realm::Overworld),
+    [x1_str, z1_str, x2_str, z2_str, realm_str] => (x1_str, z1_str, x2_str, z2_str, realm_from_str(realm_str)),
+    _ => {
+      exit!("Error parsing scan area {:?}, expected x1,z1,x2,z2[,realm]", area_str);
+    }
+  };
+
+  let (chunk_x1, chunk_z1, _) = block_coords_to_chunk_coords((coord_from_str(x1_str), 0, coord_from_str(z1_str), realm));
+  let (chunk_x2, chunk_z2, _) = block_coords_to_chunk_coords((coord_from_str(x2_str), 0, coord_from_str(z2_str), realm));
+
+  let mut chunk_coords = Vec::new();
+
+  for chunk_x in chunk_x1.min(chunk_x2)..=chunk_x1.max(chunk_x2) {
+    for chunk_z in chunk_z1.min(chunk_z2)..=chunk_z1.max(chunk_z2) {
+      chunk_coords.push((chunk_x, chunk_z, realm));
+    }
+  }
+
+  chunk_coords
+}
+
+
+// Returns the coordinates of every chunk in every region file of every realm.
+// Chunks that haven't been generated are included, and are skipped when they're read.
+pub fn all_chunk_coords(world_dir: &String) -> Vec<ChunkCoords> {
+  let mut chunk_coords = Vec::new();
+
+  for realm in [Realm::Overworld, Realm::Nether, Realm::End] {
+    let region_dir = region_dir_path(realm, world_dir);
+
+    let entries = match read_dir(&region_dir) {
+      Ok(entries) => entries,
+      Err(err) if err.kind() == ErrorKind::NotFound => continue,
+      Err(err) => {
+        exit!("Can't read directory {:?}: {}", region_dir, err);
+      }
+    };
+
+    for entry in entries.flatten() {
+      let file_name = entry.file_name();
+
+      if let Some((region_x, region_z)) = file_name.to_str().and_then(region_file_name_to_coords) {
+        for chunk_x in 32 * region_x..32 * (region_x + 1) {
+          for chunk_z in 32 * region_z..32 * (region_z + 1) {
+            chunk_coords.push((chunk_x, chunk_z, realm));
+          }
+        }
+      }
+    }
+  }
+
+  chunk_coords
+}
+
+
+// Region file names have the form "r.<region_x>.<region_z>.mca".
+fn region_file_name_to_coords(file_name: &str) -> Option<(i32, i32)> {
+  let coords_str = file_name.strip_prefix("r.")?.strip_suffix(".mca")?;
+  let (region_x_str, region_z_str) = coords_str.split_once('.')?;
+
+  Some((region_x_str.parse().ok()?, region_z_str.parse().ok()?))
+}
@@ -0,0 +1,296 @@
+use serde_json::Value;
+
+use crate::common::{EMPTY};
+
+use crate::blocks::nbt::Nbt;
+
+
+// Data versions at which the way sign text is stored changed.
+// https://minecraft.wiki/w/Data_version
+const DATA_VERSION_SIGN_SIDES: i64 = 3442; // 23w12a, Text1 to Text4 replaced by front_text and back_text
+const DATA_VERSION_NBT_TEXT_COMPONENTS: i64 = 4298; // 25w02a, text components stored as NBT instead of JSON
+
+
+// Returns the text on the front and the back of a sign, each with all of its lines
+// separated by single spaces. The back is only read when read_back_text is true,
+// and is blank otherwise, and for signs from before signs had two sides.
+pub fn extract_sign_texts(block_entity: &Nbt, data_version: i64, read_back_text: bool) -> (String, String) {
+  let sign_text = join_sign_messages(sign_messages(block_entity, "front_text", data_version));
+
+  if read_back_text && data_version >= DATA_VERSION_SIGN_SIDES {
+    let back_sign_text = join_sign_messages(sign_messages(block_entity, "back_text", data_version));
+    return (sign_text, back_sign_text);
+  }
+
+  (sign_text, EMPTY)
+}
+
+
+fn join_sign_messages(messages: Vec<String>) -> String {
+  let mut sign_text = EMPTY;
+
+  for message in messages {
+    let next_text = message.trim();
+
+    if !sign_text.is_empty() && !next_text.is_empty() {
+      sign_text.push(' ');
+    }
+    sign_text.push_str(next_text);
+  }
+
+  sign_text
+}
+
+
+fn sign_messages(block_entity: &Nbt, side_name: &str, data_version: i64) -> Vec<String> {
+  if data_version < DATA_VERSION_SIGN_SIDES {
+    return ["Text1", "Text2", "Text3", "Text4"]
+      .iter()
+      .filter_map(|name| block_entity.get(name).and_then(Nbt::as_str))
+      .map(text_from_json)
+      .collect();
+  }
+
+  let messages = match block_entity.get(side_name).and_then(|side| side.get("messages")).and_then(Nbt::as_list) {
+    Some(messages) => messages,
+    None => {
+      return vec![];
+    }
+  };
+
+  if data_version < DATA_VERSION_NBT_TEXT_COMPONENTS {
+    messages
+      .iter()
+      .filter_map(Nbt::as_str)
+      .map(text_from_json)
+      .collect()
+  } else {
+    messages
+      .iter()
+      .map(|message| flatten_text_component(&nbt_to_json(message)))
+      .collect()
+  }
+}
+
+
+// Text components were stored as JSON strings before 25w02a. Text that isn't valid JSON
+// is used as is, which is what Minecraft did with sign text written by older versions.
+fn text_from_json(json: &str) -> String {
+  match serde_json::from_str::<Value>(json) {
+    Ok(val) => flatten_text_component(&val),
+    Err(_) => json.to_string(),
+  }
+}
+
+
+// Text components stored as NBT have the same structure as JSON text components,
+// so we convert them to JSON to flatten them the same way.
+fn nbt_to_json(nbt: &Nbt) -> Value {
+  match nbt {
+    Nbt::Byte(v) => Value::from(*v),
+    Nbt::Short(v) => Value::from(*v),
+    Nbt::Int(v) => Value::from(*v),
+    Nbt::Long(v) => Value::from(*v),
+    Nbt::Float(v) => Value::from(*v),
+    Nbt::Double(v) => Value::from(*v),
+    Nbt::String(v) => Value::from(v.as_str()),
+    Nbt::List(_, items) => Value::Array(items.iter().map(nbt_to_json).collect()),
+    Nbt::Compound(entries) => {
+      Value::Object(entries.iter().map(|(name, entry)| (name.to_string(), nbt_to_json(entry))).collect())
+    },
+    Nbt::ByteArray(items) => Value::from(items.clone()),
+    Nbt::IntArray(items) => Value::from(items.clone()),
+    Nbt::LongArray(items) => Value::from(items.clone()),
+  }
+}
+
+
+// Returns the plain text of a text component, including all of its nested components.
+// https://minecraft.wiki/w/Text_component_format
+fn flatten_text_component(component: &Value) -> String {
+  match component {
+    Value::String(text) => text.to_string(),
+    Value::Number(n) => n.to_string(),
+    Value::Bool(b) => b.to_string(),
+    Value::Null => EMPTY,
+
+    // The first component of an array is the parent of the rest,
+    // so the plain text is just all of them concatenated.
+    Value::Array(components) => components.iter().map(flatten_text_component).collect(),
+
+    Value::Object(object) => {
+      let mut text = if let Some(text_val) = object.get("text") {
+        flatten_text_component(text_val)
+      } else if let Some(Value::String(key)) = object.get("translate") {
+        flatten_translation(key, object.get("fallback"), object.get("with"))
+      } else if let Some(Value::String(keybind)) = object.get("keybind") {
+        keybind.to_string()
+      } else if let Some(val) = object.get("") {
+        // Lists of NBT text components that mix strings and compounds
+        // have their strings wrapped in compounds with an empty key.
+        flatten_text_component(val)
+      } else {
+        EMPTY
+      };
+
+      if let Some(Value::Array(extra)) = object.get("extra") {
+        for extra_component in extra {
+          text.push_str(&flatten_text_component(extra_component));
+        }
+      }
+
+      text
+    }
+  }
+}
+
+
+// Without the game's language files, a translated component is rendered using its fallback
+// if it has one, and otherwise using its translation key. Either one may contain %s or %1$s
+// placeholders, which are replaced by the flattened "with" arguments.
+fn flatten_translation(key: &str, fallback: Option<&Value>, with: Option<&Value>) -> String {
+  let format = match fallback {
+    Some(Value::String(fallback)) => fallback.as_str(),
+    _ => key,
+  };
+
+  let args: Vec<String> = match with {
+    Some(Value::Array(args)) => args.iter().map(flatten_text_component).collect(),
+    _ => vec![],
+  };
+
+  let mut text = EMPTY;
+  let mut next_arg_index = 0;
+  let mut rest = format;
+
+  while let Some(percent_index) = rest.find('%') {
+    text.push_str(&rest[..percent_index]);
+    rest = &rest[percent_index + 1..];
+
+    if let Some(after) = rest.strip_prefix('%') {
+      text.push('%');
+      rest = after;
+    } else if let Some(after) = rest.strip_prefix('s') {
+      text.push_str(args.get(next_arg_index).map_or("", |arg| arg.as_str()));
+      next_arg_index += 1;
+      rest = after;
+    } else if let Some(dollar_index) = rest.find("$s") {
+      match rest[..dollar_index].parse::<usize>() {
+        Ok(arg_num) if arg_num > 0 => {
+          text.push_str(args.get(arg_num - 1).map_or("", |arg| arg.as_str()));
+          rest = &rest[dollar_index + 2..];
+        },
+        _ => {
+          text.push('%');
+        }
+      }
+    } else {
+      text.push('%');
+    }
+  }
+
+  text.push_str(rest);
+  text
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::blocks::nbt::{TAG_COMPOUND, TAG_INT, TAG_STRING};
+
+  fn sign_side(messages: &[&str]) -> Nbt {
+    Nbt::Compound(vec![
+      ("messages".to_string(), Nbt::List(TAG_STRING, messages.iter().map(|message| Nbt::String(message.to_string())).collect())),
+    ])
+  }
+
+  #[test]
+  fn reads_both_sides_of_a_sign() {
+    let block_entity = Nbt::Compound(vec![
+      ("id".to_string(), Nbt::String("minecraft:sign".to_string())),
+      ("front_text".to_string(), sign_side(&["\"~~~\"", "\"\"", "\"\"", "\"\""])),
+      ("back_text".to_string(), sign_side(&["{\"text\":\"\",\"extra\":[{\"text\":\"Cen\"},{\"text\":\"tral\"}]}", "\"\"", "\"\"", "\"\""])),
+    ]);
+
+    assert_eq!(extract_sign_texts(&block_entity, 3465, true), ("~~~".to_string(), "Central".to_string()));
+    assert_eq!(extract_sign_texts(&block_entity, 3465, false), ("~~~".to_string(), EMPTY));
+  }
+
+  fn json_sign_text(message: &str) -> String {
+    let block_entity = Nbt::Compound(vec![
+      ("front_text".to_string(), sign_side(&[message, "\"\"", "\"\"", "\"\""])),
+    ]);
+    extract_sign_texts(&block_entity, 3465, false).0
+  }
+
+  #[test]
+  fn reads_translated_json_text() {
+    assert_eq!(json_sign_text(r#"{"translate":"%2$s to %1$s via %s, 100%%","with":["Alpha",{"text":"Bravo"}]}"#), "Bravo to Alpha via Alpha, 100%");
+    assert_eq!(json_sign_text(r#"{"translate":"station.name","fallback":"%s Station","with":["Central"]}"#), "Central Station");
+    assert_eq!(json_sign_text(r#"{"translate":"station.name"}"#), "station.name");
+  }
+
+  #[test]
+  fn reads_extra_json_text() {
+    assert_eq!(json_sign_text(r#"{"text":"Cen","extra":["tr",{"text":"al","extra":[{"keybind":"!"}]}]}"#), "Central!");
+  }
+
+  #[test]
+  fn reads_nested_json_arrays() {
+    assert_eq!(json_sign_text(r#"["",["Sta",{"text":"tion"}]," ",5,true]"#), "Station 5true");
+  }
+
+  #[test]
+  fn reads_plain_and_invalid_json_text() {
+    assert_eq!(json_sign_text(r#""Central""#), "Central");
+    assert_eq!(json_sign_text("  Central  "), "Central");
+    // Not valid JSON, so used as is.
+    assert_eq!(json_sign_text("Bob's {station"), "Bob's {station");
+  }
+
+  #[test]
+  fn reads_text_lines_before_sign_sides() {
+    let block_entity = Nbt::Compound(vec![
+      ("Text1".to_string(), Nbt::String(r#"{"text":"Central"}"#.to_string())),
+      ("Text2".to_string(), Nbt::String(r#""""#.to_string())),
+      ("Text3".to_string(), Nbt::String(r#""Station""#.to_string())),
+      ("Text4".to_string(), Nbt::String(r#""""#.to_string())),
+    ]);
+
+    assert_eq!(extract_sign_texts(&block_entity, 3337, true), ("Central Station".to_string(), EMPTY));
+  }
+
+  #[test]
+  fn reads_nbt_text_components() {
+    let message = Nbt::Compound(vec![
+      ("text".to_string(), Nbt::String("Cen".to_string())),
+      // A list mixing strings and compounds has its strings wrapped in compounds with an empty key.
+      ("extra".to_string(), Nbt::List(TAG_COMPOUND, vec![
+        Nbt::Compound(vec![("".to_string(), Nbt::String("tr".to_string()))]),
+        Nbt::Compound(vec![("text".to_string(), Nbt::String("al".to_string()))]),
+      ])),
+    ]);
+    let translated = Nbt::Compound(vec![
+      ("translate".to_string(), Nbt::String("%s %s".to_string())),
+      ("with".to_string(), Nbt::List(TAG_INT, vec![Nbt::Int(4), Nbt::Int(2)])),
+    ]);
+    let side = Nbt::Compound(vec![
+      ("messages".to_string(), Nbt::List(TAG_COMPOUND, vec![
+        message,
+        Nbt::Compound(vec![("".to_string(), Nbt::String("Station".to_string()))]),
+        translated,
+        Nbt::Compound(vec![("".to_string(), Nbt::String("".to_string()))]),
+      ])),
+    ]);
+    let block_entity = Nbt::Compound(vec![("front_text".to_string(), side)]);
+
+    assert_eq!(extract_sign_texts(&block_entity, 4298, false).0, "Central Station 4 2");
+
+    // Plain string messages are text, not JSON, once text components are NBT.
+    let block_entity = Nbt::Compound(vec![
+      ("front_text".to_string(), sign_side(&["\"Central\"", "Station", "", ""])),
+    ]);
+    assert_eq!(extract_sign_texts(&block_entity, 4298, false).0, "\"Central\" Station");
+  }
+}
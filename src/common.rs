@@ -17,7 +17,7 @@ macro_rules! exit {
 }
 
 
-#[derive(Debug, Eq, PartialEq, Hash, Clone, Copy)]
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy)]
 pub enum Realm {
   Overworld,
   Nether,
@@ -31,8 +31,14 @@ pub type ChunkCoords = (i32, i32, Realm);
 
 pub type RegionCoords = (i32, i32, Realm);
 
+// A chunk that couldn't be read, and the reason why.
+pub type ChunkWarning = (ChunkCoords, String);
 
-#[derive(Debug, PartialEq, Clone, Copy)]
+// The links from each station or switch node to the nodes it's directly connected to.
+pub type RailGraph = Vec<Vec<RailLink>>;
+
+
+#[derive(Debug, Eq, PartialEq, Hash, Clone, Copy)]
 pub enum Direction {
   N = 0,
   S = 1,
@@ -81,21 +87,21 @@ impl Direction {
 }
 
 
+// Signs of every kind of wood are represented by the same block ID,
+// because the kind of wood doesn't matter to autorail.
+// Hanging signs were added after blocks stopped having numeric IDs,
+// so they're given the two values after the wall sign's.
 #[derive(Debug, PartialEq, Clone, Copy)]
 pub enum BlockID {
   UnpoweredRail = 66,
   PoweredRail = 27,
   DetectorRail = 28,
-  OakWallSign = 68,
-  SpruceWallSign = 168,
-  BirchWallSign = 268,
-  JungleWallSign = 368,
-  AcaciaWallSign = 468,
-  DarkOakWallSign = 568,
-  MangroveWallSign = 668,
-  BambooWallSign = 768,
-  CrimsonWallSign = 868,
-  WarpedWallSign = 968,
+  ActivatorRail = 157,
+  StandingSign = 63,
+  WallSign = 68,
+  HangingSign = 69,
+  WallHangingSign = 70,
+  NetherPortal = 90,
 }
 
 impl BlockID {
@@ -104,21 +110,16 @@ impl BlockID {
       BlockID::UnpoweredRail,
       BlockID::PoweredRail,
       BlockID::DetectorRail,
+      BlockID::ActivatorRail,
     ].contains(self)
   }
 
   pub fn is_sign_id(&self) -> bool {
     [
-      BlockID::OakWallSign,
-      BlockID::SpruceWallSign,
-      BlockID::BirchWallSign,
-      BlockID::JungleWallSign,
-      BlockID::AcaciaWallSign,
-      BlockID::DarkOakWallSign,
-      BlockID::MangroveWallSign,
-      BlockID::BambooWallSign,
-      BlockID::CrimsonWallSign,
-      BlockID::WarpedWallSign,
+      BlockID::StandingSign,
+      BlockID::WallSign,
+      BlockID::HangingSign,
+      BlockID::WallHangingSign,
     ].contains(self)
   }
 }
@@ -221,7 +222,10 @@ pub struct Block {
   pub id: BlockID,
   pub rail_data: RailData,
   pub sign_data: SignData,
-  pub sign_text: String
+  pub sign_text: String,
+  pub back_sign_text: String, // only read with -b
+  pub powered: bool, // powered, detector and activator rails
+  pub waterlogged: bool
 }
 
 
@@ -234,6 +238,10 @@ impl Block {
     (&self.id).is_sign_id()
   }
 
+  pub fn is_nether_portal(&self) -> bool {
+    self.id == BlockID::NetherPortal
+  }
+
   pub fn is_straight_rail(&self) -> bool {
     (&self.rail_data).is_straight()
   }
@@ -249,26 +257,74 @@ impl Block {
   pub fn is_north_south_rail(&self) -> bool {
     (&self.rail_data).is_north_south()
   }
+
+  // The two directions along a straight rail, or None for a curved rail or a block that isn't a rail.
+  pub fn straight_rail_directions(&self) -> Option<[Direction; 2]> {
+    if !self.is_rail() {
+      None
+    } else if self.is_north_south_rail() {
+      Some([Direction::N, Direction::S])
+    } else if self.is_east_west_rail() {
+      Some([Direction::W, Direction::E])
+    } else {
+      None
+    }
+  }
+
+  // The station name on a name sign. Builders often put the name on the back of the sign,
+  // facing the platform, so when the back is read and has any text, that's the name.
+  pub fn name_sign_text(&self) -> &String {
+    if self.back_sign_text.is_empty() {&self.sign_text} else {&self.back_sign_text}
+  }
+
+  // The text on the front and then the back of a sign, leaving out a blank back.
+  pub fn sign_texts(&self) -> Vec<&String> {
+    if self.back_sign_text.is_empty() {vec![&self.sign_text]} else {vec![&self.sign_text, &self.back_sign_text]}
+  }
+}
+
+
+// Matches either the name itself, e.g. "wall_sign" before 1.14,
+// or the name with any prefix, e.g. "pale_oak_wall_sign".
+fn is_block_name_or_variant(name: &str, base_name: &str) -> bool {
+  match name.strip_suffix(base_name) {
+    Some(prefix) => prefix.is_empty() || prefix.ends_with('_'),
+    None => false,
+  }
 }
 
 
+// Only vanilla blocks are recognized, so blocks from mods with the same
+// names in their own namespaces, such as "othermod:rail", are ignored.
 pub fn block_name_to_id(name: &str) -> Option<BlockID> {
+  let name = match name.split_once(':') {
+    Some(("minecraft", name)) => name,
+    Some(_) => {
+      return None;
+    },
+    None => name,
+  };
+
   let id = match name {
-    "minecraft:rail"               => BlockID::UnpoweredRail,
-    "minecraft:powered_rail"       => BlockID::PoweredRail,
-    "minecraft:detector_rail"      => BlockID::DetectorRail,
-    "minecraft:oak_wall_sign"      => BlockID::OakWallSign,
-    "minecraft:spruce_wall_sign"   => BlockID::SpruceWallSign,
-    "minecraft:birch_wall_sign"    => BlockID::BirchWallSign,
-    "minecraft:jungle_wall_sign"   => BlockID::JungleWallSign,
-    "minecraft:acacia_wall_sign"   => BlockID::AcaciaWallSign,
-    "minecraft:dark_oak_wall_sign" => BlockID::DarkOakWallSign,
-    "minecraft:mangrove_wall_sign" => BlockID::MangroveWallSign,
-    "minecraft:bamboo_wall_sign"   => BlockID::BambooWallSign,
-    "minecraft:crimson_wall_sign"  => BlockID::CrimsonWallSign,
-    "minecraft:warped_wall_sign"   => BlockID::WarpedWallSign,
+    "rail"           => BlockID::UnpoweredRail,
+    "powered_rail"   => BlockID::PoweredRail,
+    "detector_rail"  => BlockID::DetectorRail,
+    "activator_rail" => BlockID::ActivatorRail,
+    "nether_portal"  => BlockID::NetherPortal,
     _ => {
-      return None;
+      // Signs are recognized by their name, so that signs made
+      // of new kinds of wood are recognized without any changes.
+      if is_block_name_or_variant(name, "wall_hanging_sign") {
+        BlockID::WallHangingSign
+      } else if is_block_name_or_variant(name, "hanging_sign") {
+        BlockID::HangingSign
+      } else if is_block_name_or_variant(name, "wall_sign") {
+        BlockID::WallSign
+      } else if is_block_name_or_variant(name, "sign") {
+        BlockID::StandingSign
+      } else {
+        return None;
+      }
     },
   };
 
@@ -314,6 +370,29 @@ pub fn facing_to_sign_data(facing: &str) -> Option<SignData> {
 }
 
 
+// Standing signs and hanging signs that aren't attached to a wall have a rotation
+// from 0 to 15 instead of a facing. Only the four rotations that face a direction
+// exactly are converted to that direction, and the rest are treated as facing up.
+pub fn rotation_to_sign_data(rotation: &str) -> Option<SignData> {
+  let sign_data = match rotation {
+    "0"  => SignData::S,
+    "4"  => SignData::W,
+    "8"  => SignData::N,
+    "12" => SignData::E,
+    _    => {
+      match rotation.parse::<u8>() {
+        Ok(0..=15) => SignData::U,
+        _ => {
+          return None;
+        }
+      }
+    },
+  };
+
+  Some(sign_data)
+}
+
+
 #[derive(Clone)]
 pub struct Station {
   pub coords: BlockCoords,
@@ -338,6 +417,101 @@ pub struct Switch {
 }
 
 
+impl Switch {
+  // The directions of the arms of the switch, in NSWE order.
+  pub fn arms(&self) -> Vec<Direction> {
+    (0..4)
+      .filter(|direction_index| self.has_directions[*direction_index])
+      .map(Direction::from_usize)
+      .collect()
+  }
+}
+
+
+// A closed loop of rails without a station or switch on it, found by following
+// the rails from a station or switch in the given direction.
+pub struct RailLoop {
+  pub start_coords: BlockCoords,
+  pub start_direction: Direction,
+  pub loop_coords: Vec<BlockCoords>
+}
+
+
+// A link in the rail graph to a station or switch node. The rail coordinates are the rail blocks
+// followed from the "from" node to the "to" node, and are empty for links between the switch nodes
+// of the same switch.
+pub struct RailLink {
+  pub to_node_id: usize,
+  pub distance: i32,
+  pub rail_coords: Vec<BlockCoords>
+}
+
+
+// A cycle of links between station and switch nodes whose distances add up to less than zero,
+// because of negative weights. Switches would send minecarts around such a cycle forever.
+pub struct NegativeCycle {
+  pub node_ids: Vec<usize>,
+  pub distance: i32,
+  pub weight_coords: Vec<BlockCoords>
+}
+
+
+// What's found by following the rails between the stations and switches: the rail graph,
+// the shortest distances from each node to each station, the coordinates of the rails
+// that were followed, and the loops, connection problems and negative cycles found on the way.
+pub struct RailConnections {
+  pub rail_graph: RailGraph,
+  pub distances: Vec<i32>,
+  pub rail_system_coords: Vec<BlockCoords>,
+  pub rail_loops: Vec<RailLoop>,
+  pub connection_problems: Vec<RailProblem>,
+  pub negative_cycles: Vec<NegativeCycle>
+}
+
+
+// A step along the route a minecart takes from one station to another.
+pub enum RouteStep {
+  // The switch ID, the direction the minecart arrives from, and the direction it leaves in.
+  Switch(usize, Direction, Direction),
+  // The ID of a station that the minecart passes through on the way.
+  Station(usize),
+  // The last rail block in one realm, and the first rail block in the next, for a tie between realms.
+  RealmChange(BlockCoords, BlockCoords),
+}
+
+
+pub struct Route {
+  pub from_station_id: usize,
+  pub to_station_id: usize,
+  // The number of rail blocks the minecart goes along.
+  pub rail_distance: i32,
+  // The distance including weights, which is what the switches make the shortest.
+  pub weighted_distance: i32,
+  pub steps: Vec<RouteStep>
+}
+
+
+// A problem with the rail system found while building or validating it. Errors make the
+// generated functions unusable, while warnings only mean that part of the rail system is unused.
+pub struct RailProblem {
+  pub coords: BlockCoords,
+  pub is_error: bool,
+  pub description: String
+}
+
+
+pub fn move_coords(coords: BlockCoords, direction: Direction) -> BlockCoords {
+  let (x, y, z, realm) = coords;
+
+  match direction {
+    Direction::N => (x, y, z - 1, realm),
+    Direction::S => (x, y, z + 1, realm),
+    Direction::W => (x - 1, y, z, realm),
+    Direction::E => (x + 1, y, z, realm),
+  }
+}
+
+
 pub fn block_coords_to_chunk_coords(block_coords: BlockCoords) -> ChunkCoords {
   let (x, _, z, realm) = block_coords;
   (
@@ -348,6 +522,35 @@ pub fn block_coords_to_chunk_coords(block_coords: BlockCoords) -> ChunkCoords {
 }
 
 
+// One block in the Nether corresponds to eight blocks in the Overworld.
+pub const NETHER_SCALE: i32 = 8;
+
+// Going through a nether portal, the game looks for an existing portal within this many blocks
+// horizontally of the corresponding coordinates in the other realm.
+// https://minecraft.wiki/w/Nether_portal#Portal_search
+pub const OVERWORLD_PORTAL_SEARCH_RADIUS: i32 = 128;
+pub const NETHER_PORTAL_SEARCH_RADIUS: i32 = 16;
+
+
+// Returns the coordinates in the other realm that a nether portal at the given coordinates
+// leads to, and the radius that's searched for a portal around them.
+pub fn nether_portal_destination(block_coords: BlockCoords) -> Option<(BlockCoords, i32)> {
+  let (x, y, z, realm) = block_coords;
+
+  match realm {
+    Realm::Overworld => Some((
+      (x.div_euclid(NETHER_SCALE), y, z.div_euclid(NETHER_SCALE), Realm::Nether),
+      NETHER_PORTAL_SEARCH_RADIUS
+    )),
+    Realm::Nether => Some((
+      (x * NETHER_SCALE, y, z * NETHER_SCALE, Realm::Overworld),
+      OVERWORLD_PORTAL_SEARCH_RADIUS
+    )),
+    Realm::End => None,
+  }
+}
+
+
 pub fn chunk_coords_to_region_coords(chunk_coords: ChunkCoords) -> RegionCoords {
   let (x, z, realm) = chunk_coords;
   (
@@ -396,13 +599,6 @@ pub fn realm_from_str(realm_str: &str) -> Realm {
 }
 
 
-pub struct Region {
-  pub coords: RegionCoords,
-  pub data: Vec<u8>,
-  pub last_chunk_num: u32
-}
-
-
 pub fn realm_to_out_string(realm: Realm) -> String {
   match realm {
     Realm::Overworld => "",
@@ -421,15 +617,16 @@ pub fn realm_to_command_realm(realm: Realm) -> String {
 }
 
 
-pub fn read_two_byte_size(data: &Vec<u8>, index: usize) -> usize {
-  (
-    (data[index + 0] as u16) << 8 |
-    (data[index + 1] as u16) << 0
-  ) as usize
+pub fn escape_xml(s: &str) -> String {
+  s.replace('&', "&amp;")
+    .replace('<', "&lt;")
+    .replace('>', "&gt;")
+    .replace('"', "&quot;")
+    .replace('\'', "&apos;")
 }
 
 
-pub fn read_three_byte_size(data: &Vec<u8>, index: usize) -> usize {
+pub fn read_three_byte_size(data: &[u8], index: usize) -> usize {
   (
     (data[index + 0] as i32) << 16 |
     (data[index + 1] as i32) << 8 |
@@ -438,24 +635,17 @@ pub fn read_three_byte_size(data: &Vec<u8>, index: usize) -> usize {
 }
 
 
-pub fn read_four_byte_size(data: &Vec<u8>, index: usize) -> usize {
+// Unsigned, so that a corrupt size with the high bit set isn't sign-extended to a huge usize.
+pub fn read_four_byte_size(data: &[u8], index: usize) -> usize {
   (
-    (data[index + 0] as i32) << 24 |
-    (data[index + 1] as i32) << 16 |
-    (data[index + 2] as i32) << 8 |
-    (data[index + 3] as i32) << 0
+    (data[index + 0] as u32) << 24 |
+    (data[index + 1] as u32) << 16 |
+    (data[index + 2] as u32) << 8 |
+    (data[index + 3] as u32) << 0
   ) as usize
 }
 
 
-pub fn read_i32(data: &Vec<u8>) -> i32 {
-    (data[0] as i32) << 24 |
-    (data[1] as i32) << 16 |
-    (data[2] as i32) << 8 |
-    (data[3] as i32) << 0
-}
-
-
 fn sqr(x: i32) -> i32 {
   x * x
 }
@@ -558,18 +748,14 @@ pub fn complete_function(function_body: String) -> String {
 }
 
 
-pub fn get_num_nodes(distances: &Vec<i32>) -> usize {
-  (distances.len() as f64).sqrt() as usize
-}
-
-
-pub fn get_distance(distances: &Vec<i32>, num_nodes: usize, i: usize, j: usize) -> i32 {
-  distances[i * num_nodes + j]
+pub fn get_num_nodes(distances: &Vec<i32>, num_stations: usize) -> usize {
+  distances.len().checked_div(num_stations).unwrap_or(0)
 }
 
 
-pub fn set_distance(distances: &mut Vec<i32>, num_nodes: usize, i: usize, j: usize, distance: i32) {
-  distances[i * num_nodes + j] = distance;
+// The shortest distance from a station or switch node to a station.
+pub fn get_distance(distances: &Vec<i32>, num_stations: usize, node_id: usize, station_id: usize) -> i32 {
+  distances[node_id * num_stations + station_id]
 }
 
 
@@ -4,7 +4,10 @@ use crate::common::{
   Block,
   BlockCoords,
   ChunkCoords,
+  ChunkWarning,
   Direction,
+  RailLoop,
+  RailProblem,
   Station,
   StationSign,
   Switch
@@ -19,6 +22,10 @@ use crate::common::{
   find_nearest_station_id
 };
 
+use crate::rail_map::{write_rail_maps};
+
+use crate::rail_system::find_portal_ties::PortalTie;
+
 
 fn write_stations(stations: &Vec<Station>, out_path: &String) {
   let mut writer = create_writer(out_path);
@@ -121,16 +128,27 @@ fn write_switches_nearest_station(
 }
 
 
-fn write_distances(distances: &Vec<i32>, out_path: &String) {
-  let num_nodes = get_num_nodes(distances);
+// Each row holds the distances from a station or switch node to each station,
+// and the layout is described in a header.
+fn write_distances(distances: &Vec<i32>, num_stations: usize, out_path: &String) {
+  let num_nodes = get_num_nodes(distances, num_stations);
 
   let mut writer = create_writer(out_path);
 
+  writeln_out(&mut writer, out_path, format!(
+    "# {} rows by {} columns: the distance from each node (row) to each station (column), or ∞ when there's no route",
+    num_nodes, num_stations
+  ));
+  writeln_out(&mut writer, out_path,
+              "# The rows are the stations in the order of stations.tsv, then 4 rows for each switch in the order of switches.tsv, for its N, S, W and E arms".to_string());
+  writeln_out(&mut writer, out_path,
+              "# The columns are the stations only, in the order of stations.tsv".to_string());
+
   for i in 0..num_nodes {
     let mut row_string: String = "[".to_string();
 
-    for j in 0..num_nodes {
-      let distance =  get_distance(distances, num_nodes, i, j);
+    for j in 0..num_stations {
+      let distance =  get_distance(distances, num_stations, i, j);
 
       row_string.push_str(
         &format!("{}{}",
@@ -168,11 +186,13 @@ fn write_rail_blocks(
 
       let (x, y, z, realm) = rail_block.coords;
       
-      let out_string = format!("{}\t{}\t{}\t{}\t{}\t{}",
+      let out_string = format!("{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
                                x, y, z,
                                realm_to_out_string(realm),
                                rail_block.id as u32,
-                               rail_block.rail_data as u32
+                               rail_block.rail_data as u32,
+                               bool_to_int(rail_block.powered),
+                               bool_to_int(rail_block.waterlogged)
       );
       writeln_out(&mut writer, out_path, out_string);
     }
@@ -200,44 +220,180 @@ fn write_chunks(
 }
 
 
-pub fn write_diagnostics(
-  stations: &Vec<Station>,
-  station_signs: &Vec<StationSign>,
-  switches: &Vec<Switch>,
-  distances: &Vec<i32>,
-  rail_system_coords: &Vec<BlockCoords>,
-  rail_map: &HashMap<BlockCoords, Block>,
-  chunks: &Vec<(ChunkCoords, usize)>,
-  diagnostics_out_path: &String
+fn write_chunk_warnings(
+  chunk_warnings: &Vec<ChunkWarning>,
+  out_path: &String
+) {
+  let mut writer = create_writer(out_path);
+
+  for (chunk_coords, warning) in chunk_warnings {
+
+    let (x, z, realm) = chunk_coords;
+      
+    let out_string = format!("{}\t{}\t{}\t{}",
+                             x, z,
+                             realm_to_out_string(*realm),
+                             warning
+    );
+    writeln_out(&mut writer, out_path, out_string);
+  }
+}
+
+
+// Written along with the rail problems, since rails that were meant to be
+// connected to a station are often what causes the rail errors.
+pub fn write_unconnected_rails(
+  unconnected_rail_components: &Vec<Vec<BlockCoords>>,
+  out_path: &String
+) {
+  let mut writer = create_writer(out_path);
+
+  for component in unconnected_rail_components {
+
+    let (x, y, z, realm) = component[0];
+      
+    let out_string = format!("{}\t{}\t{}\t{}\t{}",
+                             x, y, z,
+                             realm_to_out_string(realm),
+                             component.len()
+    );
+    writeln_out(&mut writer, out_path, out_string);
+  }
+}
+
+
+// The portal ties are written in the same form as the ties file, so that they can be reviewed,
+// and then edited and read back in with -t.
+pub fn write_portal_ties(
+  portal_ties: &Vec<PortalTie>,
+  out_path: &String
+) {
+  let mut writer = create_writer(out_path);
+
+  for (from_coords, (to_coords, from_direction, to_direction)) in portal_ties {
+
+    let (from_x, from_y, from_z, from_realm) = from_coords;
+    let (to_x, to_y, to_z, to_realm) = to_coords;
+      
+    let out_string = format!("{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+                             from_x, from_y, from_z,
+                             realm_to_out_string(*from_realm),
+                             from_direction.to_str(),
+                             to_x, to_y, to_z,
+                             realm_to_out_string(*to_realm),
+                             to_direction.to_str()
+    );
+    writeln_out(&mut writer, out_path, out_string);
+  }
+}
+
+
+// Each rail block of each loop is on its own line, starting with the number of the loop.
+// Written along with the rail problems, which each loop is reported as.
+pub fn write_rail_loops(
+  rail_loops: &Vec<RailLoop>,
+  out_path: &String
+) {
+  let mut writer = create_writer(out_path);
+
+  for (loop_num, rail_loop) in rail_loops.iter().enumerate() {
+    for (x, y, z, realm) in &rail_loop.loop_coords {
+
+      let out_string = format!("{}\t{}\t{}\t{}\t{}",
+                               loop_num,
+                               x, y, z,
+                               realm_to_out_string(*realm)
+      );
+      writeln_out(&mut writer, out_path, out_string);
+    }
+  }
+}
+
+
+// The rail problems are written as soon as they're found, rather than with the rest
+// of the diagnostics, since errors stop autorail before any functions are written.
+pub fn write_rail_problems(
+  rail_problems: &Vec<RailProblem>,
+  out_path: &String
 ) {
+  let mut writer = create_writer(out_path);
+
+  for rail_problem in rail_problems {
+
+    let (x, y, z, realm) = rail_problem.coords;
+      
+    let out_string = format!("{}\t{}\t{}\t{}\t{}\t{}",
+                             x, y, z,
+                             realm_to_out_string(realm),
+                             if rail_problem.is_error {"error"} else {"warning"},
+                             rail_problem.description
+    );
+    writeln_out(&mut writer, out_path, out_string);
+  }
+}
+
+
+// Everything that's written to the diagnostics, which is only needed once the functions are written.
+pub struct Diagnostics {
+  pub stations: Vec<Station>,
+  pub station_signs: Vec<StationSign>,
+  pub switches: Vec<Switch>,
+  pub distances: Vec<i32>,
+  pub rail_system_coords: Vec<BlockCoords>,
+  pub rail_map: HashMap<BlockCoords, Block>,
+  pub ties_map: HashMap<BlockCoords, (BlockCoords, Direction, Direction)>,
+  pub portal_ties: Vec<PortalTie>,
+  pub chunks: Vec<(ChunkCoords, usize)>,
+  pub chunk_warnings: Vec<ChunkWarning>,
+}
+
+
+pub fn write_diagnostics(diagnostics: &Diagnostics, diagnostics_out_path: &String) {
   write_stations(
-    &stations,
+    &diagnostics.stations,
     &format!("{diagnostics_out_path}/stations.tsv"));
   
   write_station_signs(
-    &stations,
-    &station_signs,
+    &diagnostics.stations,
+    &diagnostics.station_signs,
     &format!("{diagnostics_out_path}/station-signs.tsv"));
   
   write_switches(
-    &switches,
+    &diagnostics.switches,
     &format!("{diagnostics_out_path}/switches.tsv"));
   
   write_switches_nearest_station(
-    &switches,
-    &stations,
+    &diagnostics.switches,
+    &diagnostics.stations,
     &format!("{diagnostics_out_path}/switches-nearest-station.tsv"));
   
   write_distances(
-    &distances,
+    &diagnostics.distances,
+    diagnostics.stations.len(),
     &format!("{diagnostics_out_path}/distances.dat"));
   
   write_rail_blocks(
-    &rail_system_coords,
-    &rail_map,
+    &diagnostics.rail_system_coords,
+    &diagnostics.rail_map,
     &format!("{diagnostics_out_path}/rail-blocks.tsv"));
-  
+
+  write_portal_ties(
+    &diagnostics.portal_ties,
+    &format!("{diagnostics_out_path}/portal-ties.tsv"));
+
   write_chunks(
-    &chunks,
+    &diagnostics.chunks,
     &format!("{diagnostics_out_path}/chunks.tsv"));
+
+  write_chunk_warnings(
+    &diagnostics.chunk_warnings,
+    &format!("{diagnostics_out_path}/chunk-warnings.tsv"));
+
+  write_rail_maps(
+    &diagnostics.stations,
+    &diagnostics.station_signs,
+    &diagnostics.switches,
+    &diagnostics.rail_system_coords,
+    &diagnostics.ties_map,
+    diagnostics_out_path);
 }
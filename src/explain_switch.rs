@@ -0,0 +1,106 @@
+use crate::common::{
+  BlockCoords,
+  Direction,
+  Station,
+  Switch
+};
+use crate::common::{
+  get_distance,
+  switch_node_id
+};
+
+use crate::rail_functions::switch_functions::{switch_directions};
+
+
+fn distance_to_string(distance: i32) -> String {
+  if distance == i32::MAX {"∞".to_string()} else {distance.to_string()}
+}
+
+
+// Prints how the switch function for each direction a minecart can arrive from decides which way
+// to send it: the default direction the switch is set to, the stations whose minecarts are sent
+// another way, and the distance to each station going each way. Minecarts going to a station are
+// named "S<station ID>".
+pub fn print_switch_explanation(
+  switch_coords: BlockCoords,
+  stations: &Vec<Station>,
+  switches: &Vec<Switch>,
+  distances: &Vec<i32>
+) {
+  let num_stations = stations.len();
+
+  let switch_id = match switches.iter().position(|switch| switch.coords == switch_coords) {
+    Some(switch_id) => switch_id,
+    None => {
+      exit!("No switch at {:?}, expected the coordinates of the curved rail in the middle of a switch", switch_coords);
+    }
+  };
+  let switch = &switches[switch_id];
+
+  let arms = switch.arms();
+
+  let arms_str: Vec<&str> = arms.iter().map(|direction| direction.to_str()).collect();
+
+  println!("\nSwitch at {:?} with arms {}", switch_coords, arms_str.join(" "));
+
+  for from_direction in &arms {
+    let (shortest_directions, default_direction) = switch_directions(
+      switch,
+      switch_id,
+      *from_direction,
+      num_stations,
+      distances
+    );
+
+    let to_directions: Vec<Direction> = arms
+      .iter()
+      .copied()
+      .filter(|to_direction| to_direction != from_direction)
+      .collect();
+
+    let num_default_stations = shortest_directions
+      .iter()
+      .filter(|direction| **direction == default_direction)
+      .count();
+
+    println!("\nArriving from {}, the switch is set to {} by default, which is the shortest way to {} of {} stations",
+             from_direction.to_str(),
+             default_direction.to_str(),
+             num_default_stations,
+             num_stations);
+
+    let to_directions_str: Vec<&str> = to_directions.iter().map(|direction| direction.to_str()).collect();
+    println!("Distances going {}:", to_directions_str.join(" / "));
+
+    for (station_id, shortest_direction) in shortest_directions.iter().enumerate() {
+      let arm_distances: Vec<String> = to_directions
+        .iter()
+        .map(|to_direction| {
+          distance_to_string(
+            get_distance(
+              distances,
+              num_stations,
+              switch_node_id(switch_id, *to_direction as usize, num_stations),
+              station_id
+            )
+          )
+        })
+        .collect();
+
+      // Minecarts going to a station that can't be reached are left to go the default way.
+      let decision = if shortest_direction == from_direction {
+        format!("no route, goes {} by default", default_direction.to_str())
+      } else if *shortest_direction == default_direction {
+        format!("goes {} by default", default_direction.to_str())
+      } else {
+        format!("overridden to go {}", shortest_direction.to_str())
+      };
+
+      println!("  S{} {:?}: {} -> {}",
+               station_id,
+               stations[station_id].name,
+               arm_distances.join(" / "),
+               decision);
+    }
+  }
+}
@@ -0,0 +1,332 @@
+// Exports the rail graph that the shortest routes are found in, for viewing in tools such as
+// Graphviz or Gephi, or for use by other tools. The same graph is written in three formats:
+// rail-graph.dot (DOT), rail-graph.graphml (GraphML) and rail-graph.json (JSON).
+//
+// The nodes are the stations, and the arms of the switches. The node IDs are the same as the
+// node IDs used in distances.dat, so only the switch arms that have rails are included.
+// Links from a node to itself are left out.
+//
+// The JSON has the form:
+//
+// {
+//   "nodes": [
+//     {
+//       "id": <node ID>,
+//       "kind": "station" or "switch",
+//       "name": <station name, only for stations>,
+//       "x": <x>, "y": <y>, "z": <z>,
+//       "realm": "overworld", "the_nether" or "the_end",
+//       "direction": "n", "s", "w" or "e"
+//     },
+//     ...
+//   ],
+//   "links": [
+//     {
+//       "from": <node ID>,
+//       "to": <node ID>,
+//       "distance": <distance, including weights>,
+//       "kind": "rails", or "switch" for links between the arms of the same switch,
+//       "rail_coords": [{"x": <x>, "y": <y>, "z": <z>, "realm": <realm>}, ...]
+//     },
+//     ...
+//   ]
+// }
+//
+// The direction of a station is the direction minecarts leave it in. The direction of a switch
+// node is the direction of its arm, going away from the switch. The rail coordinates are the
+// rail blocks followed from the "from" node to the "to" node, which may be in more than one realm
+// when the rails go through nether portals. The DOT and GraphML files have the same attributes,
+// with the rail coordinates written as "x y z realm" separated by semicolons.
+
+use serde_json::{json, Value};
+
+use crate::common::{
+  BlockCoords,
+  Direction,
+  RailGraph,
+  RailLink,
+  Station,
+  Switch
+};
+use crate::common::{
+  create_and_write,
+  escape_xml,
+  realm_to_command_realm,
+  switch_node_id,
+  EMPTY
+};
+
+
+struct GraphNode {
+  id: usize,
+  is_station: bool,
+  name: String,
+  coords: BlockCoords,
+  direction: Direction,
+}
+
+
+fn graph_nodes(stations: &[Station], switches: &[Switch]) -> Vec<GraphNode> {
+  let num_stations = stations.len();
+
+  let mut graph_nodes: Vec<GraphNode> = Vec::new();
+
+  for (station_id, station) in stations.iter().enumerate() {
+    graph_nodes.push(
+      GraphNode {
+        id: station_id,
+        is_station: true,
+        name: station.name.clone(),
+        coords: station.coords,
+        direction: station.direction,
+      }
+    );
+  }
+
+  for (switch_id, switch) in switches.iter().enumerate() {
+    for direction in switch.arms() {
+      graph_nodes.push(
+        GraphNode {
+          id: switch_node_id(switch_id, direction as usize, num_stations),
+          is_station: false,
+          name: EMPTY,
+          coords: switch.coords,
+          direction,
+        }
+      );
+    }
+  }
+
+  graph_nodes
+}
+
+
+// The links of the graph as (<from node ID>, <link>), leaving out the links from a node to itself.
+fn graph_links(rail_graph: &RailGraph) -> Vec<(usize, &RailLink)> {
+  let mut graph_links: Vec<(usize, &RailLink)> = Vec::new();
+
+  for (from_node_id, rail_links) in rail_graph.iter().enumerate() {
+    for rail_link in rail_links {
+      if rail_link.to_node_id != from_node_id {
+        graph_links.push((from_node_id, rail_link));
+      }
+    }
+  }
+
+  graph_links
+}
+
+
+fn node_kind(graph_node: &GraphNode) -> &str {
+  if graph_node.is_station {"station"} else {"switch"}
+}
+
+
+fn node_label(graph_node: &GraphNode) -> String {
+  if graph_node.is_station {
+    graph_node.name.clone()
+  } else {
+    let (x, y, z, _) = graph_node.coords;
+    format!("switch {} {} {} {}", x, y, z, graph_node.direction.to_str())
+  }
+}
+
+
+fn link_kind(rail_link: &RailLink) -> &str {
+  if rail_link.rail_coords.is_empty() {"switch"} else {"rails"}
+}
+
+
+fn rail_coords_to_string(rail_coords: &[BlockCoords]) -> String {
+  rail_coords
+    .iter()
+    .map(|(x, y, z, realm)| format!("{} {} {} {}", x, y, z, realm_to_command_realm(*realm)))
+    .collect::<Vec<String>>()
+    .join(";")
+}
+
+
+fn escape_dot(s: &str) -> String {
+  s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+
+fn rail_graph_to_dot(graph_nodes: &[GraphNode], graph_links: &[(usize, &RailLink)]) -> String {
+  let mut dot = "digraph rail_graph {\n".to_string();
+
+  for graph_node in graph_nodes {
+    let (x, y, z, realm) = graph_node.coords;
+
+    dot.push_str(
+      &format!("  node{} [label=\"{}\", shape={}, kind=\"{}\", name=\"{}\", x={}, y={}, z={}, realm=\"{}\", direction=\"{}\"];\n",
+               graph_node.id,
+               escape_dot(&node_label(graph_node)),
+               if graph_node.is_station {"box"} else {"ellipse"},
+               node_kind(graph_node),
+               escape_dot(&graph_node.name),
+               x, y, z,
+               realm_to_command_realm(realm),
+               graph_node.direction.to_str())
+    );
+  }
+
+  for (from_node_id, rail_link) in graph_links {
+    dot.push_str(
+      &format!("  node{} -> node{} [label=\"{}\", distance={}, kind=\"{}\", rail_coords=\"{}\"];\n",
+               from_node_id,
+               rail_link.to_node_id,
+               rail_link.distance,
+               rail_link.distance,
+               link_kind(rail_link),
+               rail_coords_to_string(&rail_link.rail_coords))
+    );
+  }
+
+  dot.push_str("}\n");
+
+  dot
+}
+
+
+fn rail_graph_to_graphml(graph_nodes: &[GraphNode], graph_links: &[(usize, &RailLink)]) -> String {
+  let mut graphml = r#"<?xml version="1.0" encoding="UTF-8"?>
+<graphml xmlns="http://graphml.graphdrawing.org/xmlns">
+  <key id="label" for="node" attr.name="label" attr.type="string"/>
+  <key id="kind" for="node" attr.name="kind" attr.type="string"/>
+  <key id="name" for="node" attr.name="name" attr.type="string"/>
+  <key id="x" for="node" attr.name="x" attr.type="int"/>
+  <key id="y" for="node" attr.name="y" attr.type="int"/>
+  <key id="z" for="node" attr.name="z" attr.type="int"/>
+  <key id="realm" for="node" attr.name="realm" attr.type="string"/>
+  <key id="direction" for="node" attr.name="direction" attr.type="string"/>
+  <key id="distance" for="edge" attr.name="distance" attr.type="int"/>
+  <key id="link_kind" for="edge" attr.name="kind" attr.type="string"/>
+  <key id="rail_coords" for="edge" attr.name="rail_coords" attr.type="string"/>
+  <graph id="rail_graph" edgedefault="directed">
+"#.to_string();
+
+  for graph_node in graph_nodes {
+    let (x, y, z, realm) = graph_node.coords;
+
+    graphml.push_str(
+      &format!(r#"    <node id="n{}">
+      <data key="label">{}</data>
+      <data key="kind">{}</data>
+      <data key="name">{}</data>
+      <data key="x">{}</data>
+      <data key="y">{}</data>
+      <data key="z">{}</data>
+      <data key="realm">{}</data>
+      <data key="direction">{}</data>
+    </node>
+"#,
+               graph_node.id,
+               escape_xml(&node_label(graph_node)),
+               node_kind(graph_node),
+               escape_xml(&graph_node.name),
+               x, y, z,
+               realm_to_command_realm(realm),
+               graph_node.direction.to_str())
+    );
+  }
+
+  for (link_num, (from_node_id, rail_link)) in graph_links.iter().enumerate() {
+    graphml.push_str(
+      &format!(r#"    <edge id="e{}" source="n{}" target="n{}">
+      <data key="distance">{}</data>
+      <data key="link_kind">{}</data>
+      <data key="rail_coords">{}</data>
+    </edge>
+"#,
+               link_num,
+               from_node_id,
+               rail_link.to_node_id,
+               rail_link.distance,
+               link_kind(rail_link),
+               rail_coords_to_string(&rail_link.rail_coords))
+    );
+  }
+
+  graphml.push_str("  </graph>\n</graphml>\n");
+
+  graphml
+}
+
+
+fn rail_graph_to_json(graph_nodes: &[GraphNode], graph_links: &[(usize, &RailLink)]) -> String {
+  let json_nodes: Vec<Value> = graph_nodes
+    .iter()
+    .map(|graph_node| {
+      let (x, y, z, realm) = graph_node.coords;
+
+      let mut json_node = json!({
+        "id": graph_node.id,
+        "kind": node_kind(graph_node),
+        "x": x,
+        "y": y,
+        "z": z,
+        "realm": realm_to_command_realm(realm),
+        "direction": graph_node.direction.to_str(),
+      });
+
+      if graph_node.is_station {
+        json_node["name"] = json!(graph_node.name);
+      }
+
+      json_node
+    })
+    .collect();
+
+  let json_links: Vec<Value> = graph_links
+    .iter()
+    .map(|(from_node_id, rail_link)| {
+      let json_rail_coords: Vec<Value> = rail_link.rail_coords
+        .iter()
+        .map(|(x, y, z, realm)| json!({"x": x, "y": y, "z": z, "realm": realm_to_command_realm(*realm)}))
+        .collect();
+
+      json!({
+        "from": from_node_id,
+        "to": rail_link.to_node_id,
+        "distance": rail_link.distance,
+        "kind": link_kind(rail_link),
+        "rail_coords": json_rail_coords,
+      })
+    })
+    .collect();
+
+  let json_graph = json!({
+    "nodes": json_nodes,
+    "links": json_links,
+  });
+
+  match serde_json::to_string(&json_graph) {
+    Ok(json_string) => json_string + "\n",
+    Err(err) => {
+      exit!("Error converting the rail graph to JSON: {}", err);
+    }
+  }
+}
+
+
+pub fn write_rail_graph(
+  stations: &Vec<Station>,
+  switches: &Vec<Switch>,
+  rail_graph: &RailGraph,
+  diagnostics_out_path: &String
+) {
+  let graph_nodes = graph_nodes(stations, switches);
+  let graph_links = graph_links(rail_graph);
+
+  create_and_write(
+    &format!("{diagnostics_out_path}/rail-graph.dot"),
+    rail_graph_to_dot(&graph_nodes, &graph_links));
+
+  create_and_write(
+    &format!("{diagnostics_out_path}/rail-graph.graphml"),
+    rail_graph_to_graphml(&graph_nodes, &graph_links));
+
+  create_and_write(
+    &format!("{diagnostics_out_path}/rail-graph.json"),
+    rail_graph_to_json(&graph_nodes, &graph_links));
+}
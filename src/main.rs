@@ -1,6 +1,11 @@
 #[macro_use]
 mod common;
 mod diagnostics;
+mod graph_export;
+mod rail_map;
+mod web_map_markers;
+mod route_output;
+mod explain_switch;
 mod in_files;
 mod blocks;
 mod rail_system;
@@ -11,14 +16,24 @@ use std::collections::{HashMap};
 use crate::common::{
   Block,
   BlockCoords,
+  ChunkCoords,
   Direction,
-  Realm};
+  RailConnections,
+  RailGraph,
+  Realm,
+  Station,
+  Switch};
 use crate::common::{
   coord_from_str,
+  realm_from_str,
   block_coords_to_chunk_coords
 };
 
-use crate::diagnostics::{write_diagnostics};
+use crate::diagnostics::{Diagnostics, write_diagnostics, write_portal_ties, write_rail_loops, write_rail_problems, write_unconnected_rails};
+use crate::graph_export::{write_rail_graph};
+use crate::web_map_markers::{write_web_map_markers};
+use crate::route_output::{print_route, write_route_json};
+use crate::explain_switch::{print_switch_explanation};
 
 use crate::in_files::{
   build_ties_map,
@@ -26,39 +41,85 @@ use crate::in_files::{
 };
 
 use crate::blocks::find_blocks::{find_blocks};
+use crate::blocks::region_cache::{RegionCache, RegionCacheCapacity};
+use crate::blocks::region_cache::{DEFAULT_MAX_CACHED_REGIONS};
+use crate::blocks::scan_chunks::{area_chunk_coords, all_chunk_coords};
+use crate::blocks::level_dat::{read_spawn_block_coords};
 
 use crate::rail_system::find_stations::{find_stations};
 use crate::rail_system::find_station_signs::{find_station_signs};
 use crate::rail_system::find_switches::{find_switches};
 use crate::rail_system::find_distances::{find_distances};
+use crate::rail_system::find_rail_components::{find_unconnected_rail_components};
+use crate::rail_system::find_portal_ties::{find_portal_ties};
+use crate::rail_system::validate_rail_system::{validate_rail_system};
+use crate::rail_system::find_route::{find_route, find_station_id_by_name};
 
 use crate::rail_functions::fixed_functions::{write_fixed_functions};
 use crate::rail_functions::system_functions::{write_system_functions};
 use crate::rail_functions::select_functions::{write_select_functions};
 
+
+// What to do once the rail system has been found.
+enum Command {
+  // Write the functions to the functions out path.
+  WriteFunctions(String),
+  // Find the route between the two named stations, and optionally write it as JSON to the out path.
+  Route(String, String, Option<String>),
+  // Explain how the switch at the coordinates decides which way to send each minecart.
+  ExplainSwitch(BlockCoords),
+}
+
+
+// The command line arguments.
+struct Options {
+  seed_strs: Vec<String>,
+  world_dir: String,
+  command: Command,
+  diagnostics_out_path_option: Option<String>,
+  ties_path_option: Option<String>,
+  weights_path_option: Option<String>,
+  read_back_text: bool,
+  region_cache_capacity_option: Option<String>,
+  use_mmap: bool,
+  scan_area_option: Option<String>,
+  scan_all: bool,
+  markers_out_path_option: Option<String>,
+  portal_ties_out_path_option: Option<String>,
+}
+
   
 fn main() {
   let args: Vec<String> = std::env::args().collect();
 
-  let (
-    block_x_str,
-    block_z_str,
+  let Options {
+    seed_strs,
     world_dir,
-    functions_out_path,
+    command,
     diagnostics_out_path_option,
     ties_path_option,
     weights_path_option,
-  ) = parse_args(&args);
-
-  let starting_chunk_coords = block_coords_to_chunk_coords((
-    coord_from_str(block_x_str.as_str()),
-    0,
-    coord_from_str(block_z_str.as_str()),
-    Realm::Overworld,
-  ));
+    read_back_text,
+    region_cache_capacity_option,
+    use_mmap,
+    scan_area_option,
+    scan_all,
+    markers_out_path_option,
+    portal_ties_out_path_option,
+  } = parse_args(&args);
+
+  // Without any seeds, the flood fill starts from the world spawn point.
+  let seed_block_coords: Vec<BlockCoords> =
+    if seed_strs.is_empty() {
+      let spawn_block_coords = read_spawn_block_coords(&world_dir);
+      println!("\nStarting from the world spawn point {:?}", spawn_block_coords);
+      vec![spawn_block_coords]
+    } else {
+      seed_strs.iter().map(|seed_str| seed_from_str(seed_str)).collect()
+    };
 
   
-  let ties_map: HashMap<BlockCoords, (BlockCoords, Direction, Direction)> =
+  let mut ties_map: HashMap<BlockCoords, (BlockCoords, Direction, Direction)> =
     if let Some(ties_path) = ties_path_option {
       println!("\nReading from ties file {:?}", ties_path);
       build_ties_map(&ties_path)
@@ -77,8 +138,40 @@ fn main() {
 
   // find all potentially relevant blocks
 
+  // Scanning an area or the whole world finds rails that aren't reachable from the starting
+  // chunk. The flood fill still continues from the scanned chunks, following rails out of the area.
+  let mut seed_chunk_coords: Vec<ChunkCoords> =
+    seed_block_coords.into_iter().map(block_coords_to_chunk_coords).collect();
+
+  if let Some(scan_area) = scan_area_option {
+    seed_chunk_coords.append(&mut area_chunk_coords(&scan_area));
+  } else if scan_all {
+    seed_chunk_coords.append(&mut all_chunk_coords(&world_dir));
+  }
+
   println!("\nReading from regions:");
-  let (blocks, chunks) = find_blocks(starting_chunk_coords, &world_dir, &ties_map);
+  let region_cache_capacity = match region_cache_capacity_option {
+    Some(capacity_str) => RegionCacheCapacity::capacity_from_str(&capacity_str),
+    None => RegionCacheCapacity::Regions(DEFAULT_MAX_CACHED_REGIONS),
+  };
+  let mut region_cache = RegionCache::new(region_cache_capacity, use_mmap);
+
+  let (blocks, chunks, chunk_warnings) = find_blocks(
+    seed_chunk_coords,
+    &world_dir,
+    read_back_text,
+    &ties_map,
+    &mut region_cache
+  );
+
+  println!("{}", region_cache.stats_string());
+
+  if !chunk_warnings.is_empty() {
+    println!("\nSkipped {} unreadable chunks:", chunk_warnings.len());
+    for (chunk_coords, warning) in &chunk_warnings {
+      println!("{:?}: {}", chunk_coords, warning);
+    }
+  }
   
   let mut rail_map: HashMap<BlockCoords, Block> = HashMap::new();
   let mut sign_map: HashMap<BlockCoords, Block> = HashMap::new();
@@ -92,12 +185,32 @@ fn main() {
   }
 
 
+  // tie rails through nether portals
+
+  println!("\nFinding portal ties");
+  let portal_ties = find_portal_ties(&blocks, &rail_map);
+
+  // Ties from the ties file take precedence over portal ties from the same rail block.
+  for (from_block_coords, tie) in &portal_ties {
+    ties_map.entry(*from_block_coords).or_insert(*tie);
+  }
+
+  if !portal_ties.is_empty() {
+    println!("Found {} portal ties", portal_ties.len());
+  }
+
+  if let Some(portal_ties_out_path) = &portal_ties_out_path_option {
+    println!("Writing portal ties to {:?}", portal_ties_out_path);
+    write_portal_ties(&portal_ties, portal_ties_out_path);
+  }
+
+
   // build rail system
       
-  println!("\nFinding stations");
+  println!("Finding stations");
   let stations = find_stations(&blocks, &rail_map, &sign_map);
 
-  if stations.len() == 0 {
+  if stations.is_empty() {
     exit!("No stations found");
   }
 
@@ -108,7 +221,14 @@ fn main() {
   let switches = find_switches(&blocks, &rail_map);
 
   println!("Finding connections and shortest routes");
-  let (distances, rail_system_coords) =
+  let RailConnections {
+    rail_graph,
+    distances,
+    rail_system_coords,
+    rail_loops,
+    connection_problems,
+    negative_cycles
+  } =
     find_distances(
       &stations,
       &switches,
@@ -116,6 +236,81 @@ fn main() {
       &ties_map,
       &weights_map
     );
+
+  println!("Finding rails not connected to a station");
+  let unconnected_rail_components = find_unconnected_rail_components(&stations, &rail_map, &ties_map);
+
+  if !unconnected_rail_components.is_empty() {
+    println!("Found {} rail components not connected to a station", unconnected_rail_components.len());
+  }
+
+  println!("Validating rail system");
+  let rail_problems = validate_rail_system(
+    &stations,
+    &switches,
+    &rail_graph,
+    &distances,
+    &rail_loops,
+    &negative_cycles,
+    connection_problems
+  );
+
+  // The rail graph is written even if there are rail errors, since it helps to find them.
+  if let Some(diagnostics_out_path) = &diagnostics_out_path_option {
+    write_rail_problems(
+      &rail_problems,
+      &format!("{diagnostics_out_path}/rail-problems.tsv"));
+
+    write_unconnected_rails(
+      &unconnected_rail_components,
+      &format!("{diagnostics_out_path}/unconnected-rails.tsv"));
+
+    write_rail_loops(
+      &rail_loops,
+      &format!("{diagnostics_out_path}/rail-loops.tsv"));
+
+    write_rail_graph(
+      &stations,
+      &switches,
+      &rail_graph,
+      diagnostics_out_path);
+  }
+
+  if !rail_problems.is_empty() {
+    println!("\nFound {} rail problems:", rail_problems.len());
+    for rail_problem in &rail_problems {
+      println!("{} at {:?}: {}",
+               if rail_problem.is_error {"Error"} else {"Warning"},
+               rail_problem.coords,
+               rail_problem.description);
+    }
+  }
+
+  let num_rail_errors = rail_problems.iter().filter(|rail_problem| rail_problem.is_error).count();
+
+  if num_rail_errors > 0 {
+    exit!("Stopping because of {} rail errors", num_rail_errors);
+  }
+
+  let functions_out_path = match command {
+    Command::WriteFunctions(functions_out_path) => functions_out_path,
+    Command::Route(from_station_name, to_station_name, route_json_out_path_option) => {
+      write_route(
+        &from_station_name,
+        &to_station_name,
+        route_json_out_path_option,
+        &stations,
+        &switches,
+        &rail_graph,
+        &distances
+      );
+      return;
+    }
+    Command::ExplainSwitch(switch_coords) => {
+      print_switch_explanation(switch_coords, &stations, &switches, &distances);
+      return;
+    }
+  };
   
 
   // write functions
@@ -136,34 +331,118 @@ fn main() {
   write_select_functions(&stations, &functions_out_path);
 
 
+  // write web map markers
+
+  if let Some(markers_out_path) = markers_out_path_option {
+    println!("\nWriting web map markers");
+    write_web_map_markers(
+      &stations,
+      &switches,
+      &rail_graph,
+      &markers_out_path
+    );
+  }
+
+
   // write diagnostics
 
   if let Some(diagnostics_out_path) = diagnostics_out_path_option {
     println!("\nWriting diagnostics");
     write_diagnostics(
-      &stations,
-      &station_signs,
-      &switches,
-      &distances,
-      &rail_system_coords,
-      &rail_map,
-      &chunks,
+      &Diagnostics {
+        stations,
+        station_signs,
+        switches,
+        distances,
+        rail_system_coords,
+        rail_map,
+        ties_map,
+        portal_ties,
+        chunks,
+        chunk_warnings,
+      },
       &diagnostics_out_path
     );
   }
 }
 
 
-fn param_from_args(args: &Vec<String>, param_prefix: &str) -> Option<String> {
+fn write_route(
+  from_station_name: &String,
+  to_station_name: &String,
+  route_json_out_path_option: Option<String>,
+  stations: &Vec<Station>,
+  switches: &Vec<Switch>,
+  rail_graph: &RailGraph,
+  distances: &Vec<i32>
+) {
+  let [from_station_id, to_station_id] = [from_station_name, to_station_name].map(|station_name| {
+    match find_station_id_by_name(stations, station_name) {
+      Some(station_id) => station_id,
+      None => {
+        exit!("No station named {:?}", station_name);
+      }
+    }
+  });
+
+  let route = match find_route(from_station_id, to_station_id, stations, switches, rail_graph, distances) {
+    Ok(route) => route,
+    Err(err) => {
+      exit!("Can't find a route: {}", err);
+    }
+  };
+
+  print_route(&route, stations, switches);
+
+  if let Some(route_json_out_path) = route_json_out_path_option {
+    write_route_json(&route, stations, switches, &route_json_out_path);
+  }
+}
+
+
+// A seed is "x,z" or "x,z,realm", in block coordinates. The realm defaults to the Overworld.
+fn seed_from_str(seed_str: &str) -> BlockCoords {
+  let split: Vec<&str> = seed_str.split(',').map(str::trim).collect();
+
+  match split[..] {
+    [x_str, z_str] => (coord_from_str(x_str), 0, coord_from_str(z_str), Realm::Overworld),
+    [x_str, z_str, realm_str] => (coord_from_str(x_str), 0, coord_from_str(z_str), realm_from_str(realm_str)),
+    _ => {
+      exit!("Error parsing seed {:?}, expected x,z[,realm]", seed_str);
+    }
+  }
+}
+
+
+// Options start with "-" followed by a letter or another "-", which distinguishes
+// them from seeds and coordinates, which may be negative.
+fn is_option(arg: &str) -> bool {
+  let mut chars = arg.chars();
+
+  chars.next() == Some('-') && chars.next().is_some_and(|c| c.is_alphabetic() || c == '-')
+}
+
+
+// For compatibility, a single seed can also be given as two separate
+// coordinates "x z", which are in the Overworld.
+fn seeds_from_args(seed_args: &[String]) -> Vec<String> {
+  if let [x_str, z_str] = seed_args {
+    if !x_str.contains(',') && !z_str.contains(',') {
+      return vec![format!("{},{}", x_str, z_str)];
+    }
+  }
+
+  seed_args.to_vec()
+}
+
+
+// The args are the options, after the command and the seeds.
+fn param_from_args(args: &[String], param_prefix: &str) -> Option<String> {
   let args_len = args.len();
 
   for (i, param) in args.iter().enumerate() {
-    if i < 3 {
-      continue;
-    }
-    
     if let Some(stripped_param) = param.strip_prefix(param_prefix) {
-      if stripped_param == "" {
+      if stripped_param.is_empty() {
         if i < args_len - 1 {
           return Some(args[i + 1].to_string());
         }
@@ -177,39 +456,87 @@ fn param_from_args(args: &Vec<String>, param_prefix: &str) -> Option<String> {
 }
 
 
-fn parse_args(args: &Vec<String>) -> (
-  String, // block_x_str
-  String, // block_z_str
-  String, // world_dir
-  String, // functions_out_path
-  Option<String>, // diagnostics_out_path_option,
-  Option<String>, // ties_path_option
-  Option<String>  // weights_path_option
-) {
-  if args.len() >= 3 {
-    let block_x_str = &args[1];
-    let block_z_str = &args[2];
-    
-    if let Some(world_dir) = param_from_args(args, "-i") {
-      if let Some(functions_out_path) = param_from_args(args, "-o") {
+fn flag_from_args(args: &[String], flag: &str) -> bool {
+  args.iter().any(|arg| arg == flag)
+}
+
+
+fn parse_args(args: &Vec<String>) -> Options {
+  // A route query starts with "route" and the names of the two stations.
+  let is_route = args.len() >= 2 && args[1] == "route";
 
-        let diagnostics_out_path_option = param_from_args(args, "-d");
-        let ties_path_option = param_from_args(args, "-t");
-        let weights_path_option = param_from_args(args, "-w");
+  // A switch explanation starts with "explain-switch" and the coordinates of the switch,
+  // optionally followed by its realm.
+  let is_explain_switch = args.len() >= 2 && args[1] == "explain-switch";
 
-        return (
-          block_x_str.to_string(),
-          block_z_str.to_string(),
+  // When the station names or switch coordinates are missing, e.g. "route A -i world", the usage
+  // is shown, rather than taking an option as a station name or the command as a seed.
+  let num_command_args = if is_route {2} else if is_explain_switch {3} else {0};
+  let has_command_args =
+    args.len() >= 2 + num_command_args &&
+    args[2..2 + num_command_args].iter().all(|arg| !is_option(arg));
+
+  let has_switch_realm = is_explain_switch && args.len() >= 6 && args[5].starts_with(char::is_alphabetic);
+
+  let first_arg_index = if is_route {4} else if is_explain_switch {if has_switch_realm {6} else {5}} else {1};
+
+  if has_command_args && args.len() > first_arg_index {
+    // The seeds are the args between the command and the first option.
+    let num_seed_args = args[first_arg_index..].iter().take_while(|arg| !is_option(arg)).count();
+
+    let seed_strs = seeds_from_args(&args[first_arg_index..first_arg_index + num_seed_args]);
+    let route_station_names = if is_route {Some((args[2].to_string(), args[3].to_string()))} else {None};
+    let switch_coords_option = if is_explain_switch {
+      Some((
+        coord_from_str(&args[2]),
+        coord_from_str(&args[3]),
+        coord_from_str(&args[4]),
+        if has_switch_realm {realm_from_str(&args[5])} else {Realm::Overworld}
+      ))
+    } else {
+      None
+    };
+    let args = &args[first_arg_index + num_seed_args..];
+
+    let command_option = match (route_station_names, switch_coords_option) {
+      (Some((from_station_name, to_station_name)), _) =>
+        Some(Command::Route(from_station_name, to_station_name, param_from_args(args, "--json"))),
+      (_, Some(switch_coords)) => Some(Command::ExplainSwitch(switch_coords)),
+      _ => param_from_args(args, "-o").map(Command::WriteFunctions),
+    };
+
+    if let Some(world_dir) = param_from_args(args, "-i") {
+      if let Some(command) = command_option {
+
+        let options = Options {
+          seed_strs,
           world_dir,
-          functions_out_path,
-          diagnostics_out_path_option,
-          ties_path_option,
-          weights_path_option
-        );
+          command,
+          diagnostics_out_path_option: param_from_args(args, "-d"),
+          ties_path_option: param_from_args(args, "-t"),
+          weights_path_option: param_from_args(args, "-w"),
+          read_back_text: flag_from_args(args, "-b"),
+          region_cache_capacity_option: param_from_args(args, "-c"),
+          use_mmap: flag_from_args(args, "-m"),
+          scan_area_option: param_from_args(args, "--scan-area"),
+          scan_all: flag_from_args(args, "--scan-all"),
+          markers_out_path_option: param_from_args(args, "--markers"),
+          portal_ties_out_path_option: param_from_args(args, "--portal-ties"),
+        };
+
+        if options.scan_area_option.is_some() && options.scan_all {
+          exit!("Only one of --scan-area and --scan-all can be used");
+        }
+
+        return options;
       }
     }
   }
 
   let command = &args[0];
-  exit!("Usage: {} <block_x> <block_z> -i <world_dir> -o <functions_out_path> [-d <diagnostics_out_path>] [-t <ties_path>] [-w <weights_path>]", command);
+  let options = "[-d <diagnostics_out_path>] [-t <ties_path>] [-w <weights_path>] [-b] [-c <region_cache_capacity>] [-m] [--scan-area <x1,z1,x2,z2[,realm]> | --scan-all] [--portal-ties <portal_ties_out_path>]";
+  exit!("Usage: {} [<block_x,block_z[,realm]> ...] -i <world_dir> -o <functions_out_path> {} [--markers <markers_out_path>]
+       {} route <from_station> <to_station> [<block_x,block_z[,realm]> ...] -i <world_dir> {} [--json <route_json_out_path>]
+       {} explain-switch <x> <y> <z> [<realm>] [<block_x,block_z[,realm]> ...] -i <world_dir> {}",
+        command, options, command, options, command, options);
 }
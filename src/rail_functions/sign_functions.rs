@@ -5,7 +5,6 @@ use crate::common::{
   create_and_write,
   create_and_writeln,
   realm_to_command_realm,
-  get_num_nodes,
   get_distance,
   EMPTY,
 };
@@ -48,8 +47,7 @@ fn station_sign_body(station_sign: &StationSign, stations: &Vec<Station>) -> Str
 fn build_station_sign_body(
   station_sign: &StationSign,
   stations: &Vec<Station>,
-  distances: &Vec<i32>,
-  num_nodes: usize
+  distances: &Vec<i32>
 ) -> String {
   let mut body = r#"data merge block *1* {front_text: {messages: ['{"text":"*2*","color":"dark_blue"}','{"text":"*3*","color":"dark_blue","clickEvent":{"action":"run_command","value":"***/signs/*4*"}}','{"text":"*5*","color":"dark_blue"}','{"text":"*6*","color":"dark_blue"}']}}"#.to_string();
   
@@ -62,7 +60,7 @@ fn build_station_sign_body(
   let eucl_distance = station_sign.distance.round();
   let rail_distance = get_distance(
     distances,
-    num_nodes,
+    stations.len(),
     station_sign.belongs_to_station_id,
     station_sign.refers_to_station_id
   );
@@ -109,8 +107,6 @@ pub fn write_sign_functions(
   distances: &Vec<i32>,
   out_path: &String
 ) {
-  let num_nodes = get_num_nodes(distances);
-
   let mut build_station_signs_body: String = EMPTY;
 
   for station_sign in station_signs {
@@ -140,8 +136,7 @@ pub fn write_sign_functions(
         build_station_sign_body(
           station_sign,
           stations,
-          distances,
-          num_nodes
+          distances
         )
       )
     );
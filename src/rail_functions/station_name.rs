@@ -2,18 +2,19 @@ use crate::common::{Station};
 use crate::common::{EMPTY};
 
 
-pub fn break_up_station_name(station: &Station) -> (String, String, String) {
+// Breaks up a station name into the three rows of a sign, or returns None
+// when the station name doesn't fit on three rows.
+pub fn station_name_rows(station_name: &str) -> Option<(String, String, String)> {
   let mut rows = [EMPTY; 3];
   let mut i = 0;
   
-  for token in station.name.split(" ") {
+  for token in station_name.split(" ") {
     // If the row is empty, we know that the token will fit.
     // If the row is non-empty, check that the row plus the new token plus a separating space will fit.
     if rows[i].len() > 0 && rows[i].len() + token.len() + 1 > 16 {
       i += 1;
       if i > 2 {
-        let coords = station.coords;
-        exit!("Station name takes more than three lines at: {:?}", coords);
+        return None;
       }
     }
     if rows[i].len() > 0 {
@@ -30,11 +31,24 @@ pub fn break_up_station_name(station: &Station) -> (String, String, String) {
     rows[0] = EMPTY;
   }
 
-  (
+  Some((
     rows[0].clone(),
     rows[1].clone(),
     rows[2].clone(),
-  )
+  ))
+}
+
+
+// Station names that are too long are found when the rail system is validated,
+// before any functions are written.
+pub fn break_up_station_name(station: &Station) -> (String, String, String) {
+  match station_name_rows(&station.name) {
+    Some(rows) => rows,
+    None => {
+      let coords = station.coords;
+      exit!("Station name takes more than three lines at: {:?}", coords);
+    }
+  }
 }
 
 pub fn make_abbreviated_station_name(station: &Station) -> String {
@@ -4,7 +4,6 @@ use crate::common::{
   complete_function,
   create_and_write,
   realm_to_command_realm,
-  get_num_nodes,
   get_distance,
   switch_node_id,
   EMPTY,
@@ -49,42 +48,67 @@ fn get_switch_rail_data(
 }
 
 
-fn switch_body(
+// The direction a minecart arriving at the switch from the "from" direction should leave in,
+// to take the shortest route to the station.
+pub fn shortest_switch_direction(
   switch: &Switch,
   switch_id: usize,
   from_direction: Direction,
+  station_id: usize,
   num_stations: usize,
-  distances: &Vec<i32>,
-  num_nodes: usize
-) -> String {
+  distances: &Vec<i32>
+) -> Direction {
+  // Set the default value of shortest_direction to the value of from_direction
+  // so that if there is no available path to the station, we can determine
+  // that later, since from_direction is not a valid direction to exit the switch.
+  let mut shortest_direction = from_direction;
+  let mut shortest_distance = i32::MAX;
+
+  for to_direction_index in 0..4 {
+    let to_direction = Direction::from_usize(to_direction_index);
+    
+    if to_direction != from_direction &&
+      switch.has_directions[to_direction_index] {
+        let distance = get_distance(
+          distances,
+          num_stations,
+          switch_node_id(switch_id, to_direction_index, num_stations),
+          station_id
+        );
+
+        if distance < shortest_distance {
+          shortest_distance = distance;
+          shortest_direction = to_direction;
+        }
+      }
+  }
+
+  shortest_direction
+}
+
+
+// Returns the direction a minecart arriving from the "from" direction should leave in for each
+// station, and the default direction the switch is set to, which is the direction that the most
+// stations are reached in. Minecarts going to the other stations are sent another way by name.
+pub fn switch_directions(
+  switch: &Switch,
+  switch_id: usize,
+  from_direction: Direction,
+  num_stations: usize,
+  distances: &Vec<i32>
+) -> (Vec<Direction>, Direction) {
   let mut shortest_directions: Vec<Direction> = Vec::new();
   let mut num_shortest_directions: Vec<i32> = vec![0; 4];
 
   for station_id in 0..num_stations {
-    // Set the default value of shortest_direction to the value of from_direction
-    // so that if there is no available path to the station, we can determine
-    // that later, since from_direction is not a valid direction to exit the switch.
-    let mut shortest_direction = from_direction;
-    let mut shortest_distance = i32::MAX;
-
-    for to_direction_index in 0..4 {
-      let to_direction = Direction::from_usize(to_direction_index);
-      
-      if to_direction != from_direction &&
-        switch.has_directions[to_direction_index] {
-          let distance = get_distance(
-            distances,
-            num_nodes,
-            switch_node_id(switch_id, to_direction_index, num_stations),
-            station_id
-          );
-
-          if distance < shortest_distance {
-            shortest_distance = distance;
-            shortest_direction = to_direction;
-          }
-        }
-    }
+    let shortest_direction = shortest_switch_direction(
+      switch,
+      switch_id,
+      from_direction,
+      station_id,
+      num_stations,
+      distances
+    );
 
     shortest_directions.push(shortest_direction);
     num_shortest_directions[shortest_direction as usize] += 1;
@@ -104,10 +128,28 @@ fn switch_body(
       }
   }
 
-  if max_to_direction == from_direction {
-    panic!("No connection to stations from switch {} coming from direction {}", switch_id, from_direction.to_str());
-  }
+  (shortest_directions, max_to_direction)
+}
+
+
+fn switch_body(
+  switch: &Switch,
+  switch_id: usize,
+  from_direction: Direction,
+  num_stations: usize,
+  distances: &Vec<i32>
+) -> String {
+  let (shortest_directions, max_to_direction) = switch_directions(
+    switch,
+    switch_id,
+    from_direction,
+    num_stations,
+    distances
+  );
 
+  // Switches always have at least three directions, so switch_directions always finds another
+  // direction to set the switch to, even when no station can be reached going that way. Switch
+  // directions that don't lead to any station are reported when the rail system is validated.
   let max_switch_rail_data = get_switch_rail_data(
     switch,
     from_direction,
@@ -199,8 +241,6 @@ pub fn write_switch_functions(
   distances: &Vec<i32>,
   out_path: &String
 ) {
-  let num_nodes = get_num_nodes(distances);
-
   let mut build_switches_body: String = EMPTY;
 
   for (switch_id, switch) in switches.iter().enumerate() {
@@ -227,8 +267,7 @@ pub fn write_switch_functions(
                         switch_id,
                         direction,
                         num_stations,
-                        distances,
-                        num_nodes
+                        distances
             )
           )
         );
@@ -0,0 +1,257 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::common::{
+  BlockCoords,
+  Direction,
+  Realm,
+  Station,
+  StationSign,
+  Switch
+};
+use crate::common::{
+  create_and_write,
+  escape_xml,
+  realm_to_command_realm
+};
+
+
+// The size in pixels of a block on the map.
+const BLOCK_SIZE: i32 = 4;
+
+// The number of blocks around the edge of the map, so that labels near the edge aren't cut off.
+const MAP_MARGIN: i32 = 16;
+
+const MAP_STYLE: &str = "
+    .rail { fill: #555555; }
+    .tie { stroke: #aa00cc; stroke-width: 0.5; stroke-dasharray: 2 1; fill: none; }
+    .switch { stroke: #dd6600; stroke-width: 0.6; fill: #dd6600; }
+    .sign { fill: #996633; }
+    .station { fill: #0055cc; }
+    .label { font-family: sans-serif; font-size: 3px; fill: #000000; }
+";
+
+
+fn coords_title(coords: BlockCoords) -> String {
+  let (x, y, z, realm) = coords;
+  format!("{} {} {} {}", x, y, z, realm_to_command_realm(realm))
+}
+
+
+// The center of a block on the map. North is up.
+fn block_center(coords: BlockCoords) -> (f64, f64) {
+  let (x, _, z, _) = coords;
+  (x as f64 + 0.5, z as f64 + 0.5)
+}
+
+
+fn direction_offset(direction: Direction) -> (f64, f64) {
+  match direction {
+    Direction::N => (0.0, -1.0),
+    Direction::S => (0.0, 1.0),
+    Direction::W => (-1.0, 0.0),
+    Direction::E => (1.0, 0.0),
+  }
+}
+
+
+// The smallest and largest x and z of the coordinates in a realm.
+struct MapBounds {
+  min_x: i32,
+  min_z: i32,
+  max_x: i32,
+  max_z: i32,
+}
+
+
+impl MapBounds {
+  fn new(coords: BlockCoords) -> MapBounds {
+    let (x, _, z, _) = coords;
+    MapBounds {min_x: x, min_z: z, max_x: x, max_z: z}
+  }
+
+  fn include(&mut self, coords: BlockCoords) {
+    let (x, _, z, _) = coords;
+    self.min_x = self.min_x.min(x);
+    self.min_z = self.min_z.min(z);
+    self.max_x = self.max_x.max(x);
+    self.max_z = self.max_z.max(z);
+  }
+}
+
+
+fn rail_map_svg(
+  realm: Realm,
+  bounds: &MapBounds,
+  stations: &[Station],
+  station_signs: &[StationSign],
+  switches: &[Switch],
+  rail_coords: &[BlockCoords],
+  ties: &[(BlockCoords, BlockCoords)]
+) -> String {
+  let min_x = bounds.min_x - MAP_MARGIN;
+  let min_z = bounds.min_z - MAP_MARGIN;
+  let width = bounds.max_x - bounds.min_x + 1 + 2 * MAP_MARGIN;
+  let height = bounds.max_z - bounds.min_z + 1 + 2 * MAP_MARGIN;
+
+  let mut svg = format!(r#"<svg xmlns="http://www.w3.org/2000/svg" viewBox="{} {} {} {}" width="{}" height="{}">
+  <style>{}  </style>
+  <rect x="{}" y="{}" width="{}" height="{}" fill="white"/>
+"#,
+                        min_x, min_z, width, height,
+                        width * BLOCK_SIZE, height * BLOCK_SIZE,
+                        MAP_STYLE,
+                        min_x, min_z, width, height);
+
+  svg.push_str("  <g class=\"rails\">\n");
+  for coords in rail_coords.iter().filter(|(_, _, _, rail_realm)| *rail_realm == realm) {
+    let (x, _, z, _) = *coords;
+    svg.push_str(
+      &format!("    <rect class=\"rail\" x=\"{}\" y=\"{}\" width=\"1\" height=\"1\"><title>rail {}</title></rect>\n",
+               x, z, coords_title(*coords))
+    );
+  }
+  svg.push_str("  </g>\n");
+
+  // A tie to or from another realm is drawn as a dashed circle around the end of the tie in this realm.
+  svg.push_str("  <g class=\"ties\">\n");
+  for (from_coords, to_coords) in ties {
+    let (_, _, _, from_realm) = *from_coords;
+    let (_, _, _, to_realm) = *to_coords;
+    let title = format!("tie from {} to {}", coords_title(*from_coords), coords_title(*to_coords));
+
+    if from_realm == realm && to_realm == realm {
+      let (from_x, from_z) = block_center(*from_coords);
+      let (to_x, to_z) = block_center(*to_coords);
+      svg.push_str(
+        &format!("    <line class=\"tie\" x1=\"{}\" y1=\"{}\" x2=\"{}\" y2=\"{}\"><title>{}</title></line>\n",
+                 from_x, from_z, to_x, to_z, title)
+      );
+    } else {
+      for coords in [from_coords, to_coords] {
+        let (_, _, _, coords_realm) = *coords;
+        if coords_realm == realm {
+          let (center_x, center_z) = block_center(*coords);
+          svg.push_str(
+            &format!("    <circle class=\"tie\" cx=\"{}\" cy=\"{}\" r=\"2\"><title>{}</title></circle>\n",
+                     center_x, center_z, title)
+          );
+        }
+      }
+    }
+  }
+  svg.push_str("  </g>\n");
+
+  svg.push_str("  <g class=\"switches\">\n");
+  for switch in switches.iter().filter(|switch| switch.coords.3 == realm) {
+    let (center_x, center_z) = block_center(switch.coords);
+
+    let arms = switch.arms();
+
+    let arms_str: Vec<&str> = arms.iter().map(|direction| direction.to_str()).collect();
+
+    svg.push_str(
+      &format!("    <g class=\"switch\"><title>switch {} arms {}</title>\n",
+               coords_title(switch.coords), arms_str.join(" "))
+    );
+
+    for direction in &arms {
+      let (offset_x, offset_z) = direction_offset(*direction);
+      svg.push_str(
+        &format!("      <line x1=\"{}\" y1=\"{}\" x2=\"{}\" y2=\"{}\"/>\n",
+                 center_x, center_z, center_x + 2.0 * offset_x, center_z + 2.0 * offset_z)
+      );
+    }
+
+    svg.push_str(&format!("      <circle cx=\"{}\" cy=\"{}\" r=\"0.8\"/>\n    </g>\n", center_x, center_z));
+  }
+  svg.push_str("  </g>\n");
+
+  svg.push_str("  <g class=\"signs\">\n");
+  for station_sign in station_signs.iter().filter(|station_sign| station_sign.coords.3 == realm) {
+    let (x, _, z, _) = station_sign.coords;
+    svg.push_str(
+      &format!("    <rect class=\"sign\" x=\"{}\" y=\"{}\" width=\"1\" height=\"1\"><title>sign {} at station {} for station {}</title></rect>\n",
+               x, z,
+               coords_title(station_sign.coords),
+               escape_xml(&stations[station_sign.belongs_to_station_id].name),
+               escape_xml(&stations[station_sign.refers_to_station_id].name))
+    );
+  }
+  svg.push_str("  </g>\n");
+
+  svg.push_str("  <g class=\"stations\">\n");
+  for station in stations.iter().filter(|station| station.coords.3 == realm) {
+    let (center_x, center_z) = block_center(station.coords);
+    let name = escape_xml(&station.name);
+
+    svg.push_str(
+      &format!("    <g><title>station {} {} going {}</title>\n      <circle class=\"station\" cx=\"{}\" cy=\"{}\" r=\"1.5\"/>\n      <text class=\"label\" x=\"{}\" y=\"{}\">{}</text>\n    </g>\n",
+               name,
+               coords_title(station.coords),
+               station.direction.to_str(),
+               center_x, center_z,
+               center_x + 2.0, center_z + 1.0,
+               name)
+    );
+  }
+  svg.push_str("  </g>\n");
+
+  svg.push_str("</svg>\n");
+
+  svg
+}
+
+
+// Draws a top-down map of the rail system in each realm that has any of it, as rail-map-<realm>.svg,
+// where the realm is "overworld", "the_nether" or "the_end".
+// Every element has a title with its coordinates, which is shown when hovering over it.
+pub fn write_rail_maps(
+  stations: &Vec<Station>,
+  station_signs: &Vec<StationSign>,
+  switches: &Vec<Switch>,
+  rail_system_coords: &Vec<BlockCoords>,
+  ties_map: &HashMap<BlockCoords, (BlockCoords, Direction, Direction)>,
+  diagnostics_out_path: &String
+) {
+  // The rails between two nodes are followed in both directions, so most rail blocks appear twice.
+  let mut rail_coords: Vec<BlockCoords> = rail_system_coords
+    .iter()
+    .copied()
+    .collect::<HashSet<BlockCoords>>()
+    .into_iter()
+    .collect();
+  rail_coords.sort();
+
+  let mut ties: Vec<(BlockCoords, BlockCoords)> = ties_map
+    .iter()
+    .map(|(from_coords, (to_coords, _, _))| (*from_coords, *to_coords))
+    .collect();
+  ties.sort();
+
+  let all_coords = rail_coords
+    .iter()
+    .copied()
+    .chain(stations.iter().map(|station| station.coords))
+    .chain(station_signs.iter().map(|station_sign| station_sign.coords))
+    .chain(switches.iter().map(|switch| switch.coords))
+    .chain(ties.iter().flat_map(|(from_coords, to_coords)| [*from_coords, *to_coords]));
+
+  let mut realm_bounds: HashMap<Realm, MapBounds> = HashMap::new();
+
+  for coords in all_coords {
+    let (_, _, _, realm) = coords;
+    realm_bounds
+      .entry(realm)
+      .and_modify(|bounds| bounds.include(coords))
+      .or_insert_with(|| MapBounds::new(coords));
+  }
+
+  for realm in [Realm::Overworld, Realm::Nether, Realm::End] {
+    if let Some(bounds) = realm_bounds.get(&realm) {
+      create_and_write(
+        &format!("{}/rail-map-{}.svg", diagnostics_out_path, realm_to_command_realm(realm)),
+        rail_map_svg(realm, bounds, stations, station_signs, switches, &rail_coords, &ties)
+      );
+    }
+  }
+}
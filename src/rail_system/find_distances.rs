@@ -1,16 +1,21 @@
-use std::collections::{HashMap};
+use std::collections::{HashMap, BTreeMap, BinaryHeap};
+use std::cmp::Reverse;
 
 use crate::common::{
   Block,
   BlockCoords,
   Direction,
+  NegativeCycle,
   RailData,
+  RailConnections,
+  RailGraph,
+  RailLink,
+  RailLoop,
+  RailProblem,
   Station,
   Switch
 };
 use crate::common::{
-  get_distance,
-  set_distance,
   switch_node_id
 };
 
@@ -21,7 +26,7 @@ pub fn find_distances(
   rail_map: &HashMap<BlockCoords, Block>,
   ties_map: &HashMap<BlockCoords, (BlockCoords, Direction, Direction)>,
   weights_map: &HashMap<BlockCoords, i32>
-) -> (Vec<i32>, Vec<BlockCoords>) {
+) -> RailConnections {
   
   let num_stations = stations.len();
   let mut station_id_map: HashMap<BlockCoords, usize> = HashMap::new();
@@ -35,44 +40,65 @@ pub fn find_distances(
     switch_id_map.insert(switches[switch_id].coords, switch_id);
   }
 
+  let connection_maps = ConnectionMaps {
+    station_id_map: &station_id_map,
+    switch_id_map: &switch_id_map,
+    rail_map,
+    ties_map,
+    weights_map
+  };
+
   let num_nodes = num_stations + 4 * num_switches;
   
+  // The links from each node to the nodes it's directly connected to. Links are kept in maps
+  // so that a later link between the same two nodes replaces an earlier one.
   // Distances have type i32 instead of u32, because it's possible
   // to have negative distances when negative weights are used.
-  let mut distances: Vec<i32> = vec![i32::MAX; num_nodes * num_nodes];
+  let mut links: Vec<BTreeMap<usize, (i32, Vec<BlockCoords>)>> = vec![BTreeMap::new(); num_nodes];
 
   let mut rail_system_coords: Vec<BlockCoords> = Vec::new();
 
+  // Connections that loop back on themselves without reaching a station or switch,
+  // or that come to a dead end, are left out of the graph, and reported.
+  let mut rail_loops: Vec<RailLoop> = Vec::new();
+  let mut rail_problems: Vec<RailProblem> = Vec::new();
+
   for from_station_id in 0..num_stations {
     let from_station = &stations[from_station_id];
 
-    let (to_node_id, distance, mut rail_connection_coords) =
+    let connection_result =
       find_connection(
         from_station.coords,
         from_station.direction,
-        &station_id_map,
-        &switch_id_map,
-        rail_map,
-        ties_map,
-        weights_map,
+        &connection_maps,
         num_stations
       );
 
-    rail_system_coords.append(&mut rail_connection_coords);
+    match connection_result {
+      Ok((to_node_id, distance, rail_connection_coords)) => {
+        rail_system_coords.extend(&rail_connection_coords);
 
-    // We subtract one from the distance for each end of the connection that is a switch node,
-    // so that the distance to/from a switch node is less than the distance to/from the other
-    // switch nodes on the same switch.
-    // In this case the "from" node is a station node, so we subtract either 0 or 1,
-    // depending on whether the "to" node is a station or a switch.
-    let subtraction_distance = if is_switch_node(to_node_id, num_stations) {1} else {0};
+        // We subtract one from the distance for each end of the connection that is a switch node,
+        // so that the distance to/from a switch node is less than the distance to/from the other
+        // switch nodes on the same switch.
+        // In this case the "from" node is a station node, so we subtract either 0 or 1,
+        // depending on whether the "to" node is a station or a switch.
+        let subtraction_distance = if is_switch_node(to_node_id, num_stations) {1} else {0};
 
-    // Link the "from" station node to the node that was found to be connected to it,
-    // with the found distance minus the subtraction distance.
-    set_distance(&mut distances, num_nodes, from_station_id, to_node_id, distance - subtraction_distance);
+        // Link the "from" station node to the node that was found to be connected to it,
+        // with the found distance minus the subtraction distance.
+        links[from_station_id].insert(to_node_id, (distance - subtraction_distance, rail_connection_coords));
+      },
+      Err(ConnectionError::Loop(rail_loop)) => {
+        rail_loops.push(rail_loop);
+      },
+      Err(ConnectionError::Problem(rail_problem)) => {
+        rail_problems.push(rail_problem);
+      }
+    }
 
     // Link the "from" station node to itself with zero distance.
-    set_distance(&mut distances, num_nodes, from_station_id, from_station_id, 0);
+    links[from_station_id].insert(from_station_id, (0, Vec::new()));
   }
 
   for from_switch_id in 0..num_switches {
@@ -80,32 +106,38 @@ pub fn find_distances(
 
     for from_direction_index in 0..4 { // NSWE
       if from_switch.has_directions[from_direction_index] {
-        
-        let (to_node_id, distance, mut rail_connection_coords) =
+        let from_switch_node_id = switch_node_id(from_switch_id, from_direction_index, num_stations);
+
+        let connection_result =
           find_connection(
             from_switch.coords,
             Direction::from_usize(from_direction_index),
-            &station_id_map,
-            &switch_id_map,
-            rail_map,
-            ties_map,
-            weights_map,
+            &connection_maps,
             num_stations
           );
 
-        rail_system_coords.append(&mut rail_connection_coords);
-
-        // We subtract one from the distance for each end of the connection that is a switch node,
-        // so that the distance to/from a switch node is less than the distance to/from the other
-        // switch nodes on the same switch.
-        // In this case the "from" node is a switch node, so we subtract either 1 or 2,
-        // depending on whether the "to" node is a station or a switch.
-        let subtraction_distance = if is_switch_node(to_node_id, num_stations) {2} else {1};
-
-        // Link the "from" switch node to the node that was found to be connected to it,
-        // with the found distance minus the subtraction distance.
-        let from_switch_node_id = switch_node_id(from_switch_id, from_direction_index, num_stations);
-        set_distance(&mut distances, num_nodes, from_switch_node_id, to_node_id, distance - subtraction_distance);
+        match connection_result {
+          Ok((to_node_id, distance, rail_connection_coords)) => {
+            rail_system_coords.extend(&rail_connection_coords);
+
+            // We subtract one from the distance for each end of the connection that is a switch node,
+            // so that the distance to/from a switch node is less than the distance to/from the other
+            // switch nodes on the same switch.
+            // In this case the "from" node is a switch node, so we subtract either 1 or 2,
+            // depending on whether the "to" node is a station or a switch.
+            let subtraction_distance = if is_switch_node(to_node_id, num_stations) {2} else {1};
+
+            // Link the "from" switch node to the node that was found to be connected to it,
+            // with the found distance minus the subtraction distance.
+            links[from_switch_node_id].insert(to_node_id, (distance - subtraction_distance, rail_connection_coords));
+          },
+          Err(ConnectionError::Loop(rail_loop)) => {
+            rail_loops.push(rail_loop);
+          },
+          Err(ConnectionError::Problem(rail_problem)) => {
+            rail_problems.push(rail_problem);
+          }
+        }
 
         // Link the "from" switch node to the other switch nodes on the same switch with distance 2,
         // to compensate for the subtractions for starting or ending at a switch node.
@@ -114,91 +146,401 @@ pub fn find_distances(
             if from_switch.has_directions[to_direction_index] {
 
               let to_switch_node_id = switch_node_id(from_switch_id, to_direction_index, num_stations);
-              set_distance(&mut distances, num_nodes, from_switch_node_id, to_switch_node_id, 2);
+              links[from_switch_node_id].insert(to_switch_node_id, (2, Vec::new()));
             }
           }
         }
 
         // Link the "from" switch node to itself with zero distance.
-        set_distance(&mut distances, num_nodes, from_switch_node_id, from_switch_node_id, 0);
+        links[from_switch_node_id].insert(from_switch_node_id, (0, Vec::new()));
       }
     }
   }
 
-  floyd_warshall(&mut distances, num_nodes);
+  let rail_graph: RailGraph = links
+    .into_iter()
+    .map(|node_links| {
+      node_links
+        .into_iter()
+        .map(|(to_node_id, (distance, rail_coords))| RailLink {to_node_id, distance, rail_coords})
+        .collect()
+    })
+    .collect();
+
+  let distances = find_station_distances(&rail_graph, num_stations);
+
+  let negative_cycles: Vec<NegativeCycle> = find_negative_cycles(&rail_graph)
+    .into_iter()
+    .map(|node_ids| make_negative_cycle(node_ids, &rail_graph, weights_map))
+    .collect();
+
+  RailConnections {
+    rail_graph,
+    distances,
+    rail_system_coords,
+    rail_loops,
+    connection_problems: rail_problems,
+    negative_cycles
+  }
+}
+
 
-  (distances, rail_system_coords)
+fn find_link(rail_graph: &RailGraph, from_node_id: usize, to_node_id: usize) -> Option<&RailLink> {
+  rail_graph[from_node_id]
+    .iter()
+    .find(|rail_link| rail_link.to_node_id == to_node_id)
 }
 
 
-fn is_switch_node(node_id: usize, num_stations: usize) -> bool {
-  node_id >= num_stations
+fn make_negative_cycle(
+  node_ids: Vec<usize>,
+  rail_graph: &RailGraph,
+  weights_map: &HashMap<BlockCoords, i32>
+) -> NegativeCycle {
+  let mut distance = 0;
+  let mut weight_coords: Vec<BlockCoords> = Vec::new();
+
+  for (index, from_node_id) in node_ids.iter().enumerate() {
+    let to_node_id = node_ids[(index + 1) % node_ids.len()];
+
+    if let Some(rail_link) = find_link(rail_graph, *from_node_id, to_node_id) {
+      distance += rail_link.distance;
+
+      for coords in &rail_link.rail_coords {
+        if weights_map.get(coords).is_some_and(|weight| *weight < 0) && !weight_coords.contains(coords) {
+          weight_coords.push(*coords);
+        }
+      }
+    }
+  }
+
+  NegativeCycle {
+    node_ids,
+    distance,
+    weight_coords
+  }
 }
 
 
-fn floyd_warshall(distances: &mut Vec<i32>, num_nodes: usize) {
-  // The Floyd-Warshall algorithm is used to fill in the shortest distances for nodes
-  // that are not directly connected.
-  // https://en.wikipedia.org/wiki/Floyd%E2%80%93Warshall_algorithm
+// Finds the cycles of links whose distances add up to less than zero. Going around such a cycle
+// makes the distances shorter every time, so the shortest distances are meaningless.
+// Every node starts with a distance of zero, as if there were an extra node linked to all of them,
+// so that the cycles are found wherever they are. If the distances are still getting shorter
+// after as many rounds as there are nodes, following the previous nodes back from a node that
+// changed in the last round leads into a negative cycle.
+// https://en.wikipedia.org/wiki/Bellman%E2%80%93Ford_algorithm#Finding_negative_cycles
+fn find_negative_cycles(rail_graph: &RailGraph) -> Vec<Vec<usize>> {
+  let num_nodes = rail_graph.len();
+
+  let mut distances: Vec<i32> = vec![0; num_nodes];
+  let mut prev_node_ids: Vec<Option<usize>> = vec![None; num_nodes];
+  let mut changed_node_ids: Vec<usize> = Vec::new();
+
+  for _ in 0..=num_nodes {
+    changed_node_ids.clear();
+
+    for node_id in 0..num_nodes {
+      for rail_link in &rail_graph[node_id] {
+        let next_node_id = rail_link.to_node_id;
+        let next_distance = distances[node_id] + rail_link.distance;
+
+        if next_distance < distances[next_node_id] {
+          distances[next_node_id] = next_distance;
+          prev_node_ids[next_node_id] = Some(node_id);
+          changed_node_ids.push(next_node_id);
+        }
+      }
+    }
 
-  for k in 0..num_nodes {
-    for i in 0..num_nodes {
-      for j in 0..num_nodes {
-        let ij_dist = get_distance(distances, num_nodes, i, j);
-        let ik_dist = get_distance(distances, num_nodes, i, k);
-        let kj_dist = get_distance(distances, num_nodes, k, j);
+    if changed_node_ids.is_empty() {
+      break;
+    }
+  }
 
+  let mut is_in_cycle: Vec<bool> = vec![false; num_nodes];
+  let mut negative_cycles: Vec<Vec<usize>> = Vec::new();
 
-        if ik_dist < i32::MAX && kj_dist < i32::MAX {
-          let sum_dist = ik_dist + kj_dist;
-          if sum_dist < ij_dist {
-            set_distance(distances, num_nodes, i, j, sum_dist);
-          }
+  for changed_node_id in &changed_node_ids {
+    let mut node_id = *changed_node_id;
+
+    for _ in 0..num_nodes {
+      match prev_node_ids[node_id] {
+        Some(prev_node_id) => node_id = prev_node_id,
+        None => break,
+      }
+    }
+
+    if is_in_cycle[node_id] || prev_node_ids[node_id].is_none() {
+      continue;
+    }
+
+    let mut cycle_node_ids: Vec<usize> = vec![node_id];
+    let mut prev_node_id = prev_node_ids[node_id];
+
+    while let Some(cycle_node_id) = prev_node_id {
+      if cycle_node_id == node_id {
+        break;
+      }
+      cycle_node_ids.push(cycle_node_id);
+      prev_node_id = prev_node_ids[cycle_node_id];
+    }
+
+    // The previous nodes lead around the cycle backwards.
+    cycle_node_ids.reverse();
+
+    // Start the cycle at its lowest node ID, so that the same cycle is always reported the same way.
+    if let Some(min_index) = (0..cycle_node_ids.len()).min_by_key(|index| cycle_node_ids[*index]) {
+      cycle_node_ids.rotate_left(min_index);
+    }
+
+    for cycle_node_id in &cycle_node_ids {
+      is_in_cycle[*cycle_node_id] = true;
+    }
+    negative_cycles.push(cycle_node_ids);
+  }
+
+  negative_cycles.sort();
+
+  negative_cycles
+}
+
+
+// Only the distances to stations are needed, so rather than finding the shortest distances
+// between all pairs of nodes, the shortest distances from every node to each station are found
+// by searching backwards from the station. The distances are indexed by node ID, then station ID.
+fn find_station_distances(rail_graph: &RailGraph, num_stations: usize) -> Vec<i32> {
+  let num_nodes = rail_graph.len();
+
+  // The links of the graph reversed, from each node to the nodes that link to it.
+  let mut reversed_links: Vec<Vec<(usize, i32)>> = vec![Vec::new(); num_nodes];
+
+  for (from_node_id, node_links) in rail_graph.iter().enumerate() {
+    for rail_link in node_links {
+      reversed_links[rail_link.to_node_id].push((from_node_id, rail_link.distance));
+    }
+  }
+
+  let mut distances: Vec<i32> = vec![i32::MAX; num_nodes * num_stations];
+
+  // Negative weights can make the distances of links negative, which Dijkstra's algorithm
+  // doesn't allow. So the links are reweighted with potentials found with a single search,
+  // which makes all of their distances non-negative while keeping the same shortest paths,
+  // and the distances found with the reweighted links are then converted back.
+  // https://en.wikipedia.org/wiki/Johnson%27s_algorithm
+  let potentials = match find_potentials(&reversed_links) {
+    Some(potentials) => potentials,
+    None => {
+      // There's a negative cycle, so there are no shortest distances. It's reported
+      // as a rail error, and the distances are only used to report other problems.
+      for station_id in 0..num_stations {
+        for (node_id, distance) in bellman_ford(&reversed_links, station_id).into_iter().enumerate() {
+          distances[node_id * num_stations + station_id] = distance;
         }
       }
+      return distances;
+    }
+  };
+
+  let reweighted_links: Vec<Vec<(usize, i32)>> = reversed_links
+    .iter()
+    .enumerate()
+    .map(|(node_id, node_links)| {
+      node_links
+        .iter()
+        .map(|(next_node_id, distance)| (*next_node_id, distance + potentials[node_id] - potentials[*next_node_id]))
+        .collect()
+    })
+    .collect();
+
+  for station_id in 0..num_stations {
+    for (node_id, distance) in dijkstra(&reweighted_links, station_id).into_iter().enumerate() {
+      if distance < i32::MAX {
+        distances[node_id * num_stations + station_id] = distance - potentials[station_id] + potentials[node_id];
+      }
     }
   }
+
+  distances
+}
+
+
+// Returns a potential for each node such that adding the potential of the "from" node and
+// subtracting the potential of the "to" node makes the distance of every link non-negative,
+// or None if there's a negative cycle. The potentials are the shortest distances to each node
+// from an extra node linked to all of them with zero distance, found with Bellman-Ford.
+fn find_potentials(links: &[Vec<(usize, i32)>]) -> Option<Vec<i32>> {
+  let num_nodes = links.len();
+
+  let mut potentials: Vec<i32> = vec![0; num_nodes];
+
+  for _ in 0..=num_nodes {
+    let mut is_changed = false;
+
+    for node_id in 0..num_nodes {
+      for (next_node_id, link_distance) in &links[node_id] {
+        let next_potential = potentials[node_id] + link_distance;
+
+        if next_potential < potentials[*next_node_id] {
+          potentials[*next_node_id] = next_potential;
+          is_changed = true;
+        }
+      }
+    }
+
+    if !is_changed {
+      return Some(potentials);
+    }
+  }
+
+  None
+}
+
+
+// Returns the shortest distances from the start node to every node, following the given links.
+// The distances must not be negative.
+// https://en.wikipedia.org/wiki/Dijkstra%27s_algorithm
+fn dijkstra(links: &[Vec<(usize, i32)>], start_node_id: usize) -> Vec<i32> {
+  let mut distances: Vec<i32> = vec![i32::MAX; links.len()];
+  let mut queue: BinaryHeap<Reverse<(i32, usize)>> = BinaryHeap::new();
+
+  distances[start_node_id] = 0;
+  queue.push(Reverse((0, start_node_id)));
+
+  while let Some(Reverse((distance, node_id))) = queue.pop() {
+    if distance > distances[node_id] {
+      continue;
+    }
+
+    for (next_node_id, link_distance) in &links[node_id] {
+      let next_distance = distance + link_distance;
+
+      if next_distance < distances[*next_node_id] {
+        distances[*next_node_id] = next_distance;
+        queue.push(Reverse((next_distance, *next_node_id)));
+      }
+    }
+  }
+
+  distances
+}
+
+
+// Returns the shortest distances from the start node to every node, following the given links,
+// some of which may have negative distances. Only used when there's a negative cycle,
+// since the rounds are limited to the number of nodes rather than going on forever.
+// https://en.wikipedia.org/wiki/Bellman%E2%80%93Ford_algorithm
+fn bellman_ford(links: &[Vec<(usize, i32)>], start_node_id: usize) -> Vec<i32> {
+  let num_nodes = links.len();
+
+  let mut distances: Vec<i32> = vec![i32::MAX; num_nodes];
+  distances[start_node_id] = 0;
+
+  for _ in 1..num_nodes {
+    let mut is_changed = false;
+
+    for node_id in 0..num_nodes {
+      let distance = distances[node_id];
+
+      if distance == i32::MAX {
+        continue;
+      }
+
+      for (next_node_id, link_distance) in &links[node_id] {
+        let next_distance = distance + link_distance;
+
+        if next_distance < distances[*next_node_id] {
+          distances[*next_node_id] = next_distance;
+          is_changed = true;
+        }
+      }
+    }
+
+    if !is_changed {
+      break;
+    }
+  }
+
+  distances
+}
+
+
+enum ConnectionError {
+  Loop(RailLoop),
+  Problem(RailProblem),
+}
+
+
+fn is_switch_node(node_id: usize, num_stations: usize) -> bool {
+  node_id >= num_stations
+}
+
+
+// The maps used to follow the rails from a station or switch to the next station or switch.
+struct ConnectionMaps<'a> {
+  station_id_map: &'a HashMap<BlockCoords, usize>,
+  switch_id_map: &'a HashMap<BlockCoords, usize>,
+  rail_map: &'a HashMap<BlockCoords, Block>,
+  ties_map: &'a HashMap<BlockCoords, (BlockCoords, Direction, Direction)>,
+  weights_map: &'a HashMap<BlockCoords, i32>
 }
 
 
 fn find_connection(
   start_coords: BlockCoords,
   start_direction: Direction,
-  station_id_map: &HashMap<BlockCoords, usize>,
-  switch_id_map: &HashMap<BlockCoords, usize>,
-  rail_map: &HashMap<BlockCoords, Block>,
-  ties_map: &HashMap<BlockCoords, (BlockCoords, Direction, Direction)>,
-  weights_map: &HashMap<BlockCoords, i32>,
+  connection_maps: &ConnectionMaps,
   num_stations: usize
-) -> (usize, i32, Vec<BlockCoords>) {
+) -> Result<(usize, i32, Vec<BlockCoords>), ConnectionError> {
   let mut coords = start_coords;
   let mut direction = start_direction;
   let mut distance: i32 = 0;
 
   let mut rail_connection_coords: Vec<BlockCoords> = vec![start_coords];
 
+  // The index in the rail connection coords of each rail block that was reached, and the
+  // direction it was reached going in. Reaching a rail block going in the same direction
+  // a second time means that the rails loop back on themselves without a station or switch,
+  // and would be followed forever.
+  let mut visited_indices: HashMap<(BlockCoords, Direction), usize> = HashMap::new();
+
   loop {
     let prev_direction = direction;
 
-    (coords, direction) = find_next_rail_block(
+    (coords, direction) = match find_next_rail_block(
       coords,
       direction,
-      rail_map,
-      ties_map
-    );
+      connection_maps.rail_map,
+      connection_maps.ties_map
+    ) {
+      Ok(next) => next,
+      Err(description) => {
+        return Err(
+          ConnectionError::Problem(
+            RailProblem {
+              coords,
+              is_error: true,
+              description: format!("{}, following the rails going {} from {:?}",
+                                   description,
+                                   start_direction.to_str(),
+                                   start_coords)
+            }
+          )
+        );
+      }
+    };
 
     rail_connection_coords.push(coords);
 
     distance += 1;
-    if let Some(weight) = weights_map.get(&coords) {
+    if let Some(weight) = connection_maps.weights_map.get(&coords) {
       distance += weight;
     }
 
-    if let Some(to_station_id) = station_id_map.get(&coords) {
-      return (*to_station_id, distance, rail_connection_coords);
+    if let Some(to_station_id) = connection_maps.station_id_map.get(&coords) {
+      return Ok((*to_station_id, distance, rail_connection_coords));
     }
 
-    if let Some(to_switch_id) = switch_id_map.get(&coords) {
+    if let Some(to_switch_id) = connection_maps.switch_id_map.get(&coords) {
       
       // The previous direction is the direction going into the switch.
       // We use the previous direction as the switch direction to compute the "to" switch node ID
@@ -208,7 +550,21 @@ fn find_connection(
       // because switch nodes' directions are labeled as if traveling away from the switch.
       
       let to_switch_node_id = switch_node_id(*to_switch_id, prev_direction.opposite_direction() as usize, num_stations);
-      return (to_switch_node_id, distance, rail_connection_coords);
+      return Ok((to_switch_node_id, distance, rail_connection_coords));
+    }
+
+    let index = rail_connection_coords.len() - 1;
+
+    if let Some(loop_start_index) = visited_indices.insert((coords, direction), index) {
+      return Err(
+        ConnectionError::Loop(
+          RailLoop {
+            start_coords,
+            start_direction,
+            loop_coords: rail_connection_coords[loop_start_index..index].to_vec(),
+          }
+        )
+      );
     }
   }
 }
@@ -334,16 +690,18 @@ fn blocks_to_check(current_rail_data: RailData, current_direction: Direction) ->
 }
 
 
+// Returns the next rail block and the direction going from it, or a description
+// of why the rails can't be followed any further.
 fn find_next_rail_block(
   coords: BlockCoords,
   direction: Direction,
   rail_map: &HashMap<BlockCoords, Block>,
   ties_map: &HashMap<BlockCoords, (BlockCoords, Direction, Direction)>
-) -> (BlockCoords, Direction) {
+) -> Result<(BlockCoords, Direction), String> {
 
   if let Some((tie_to_coords, tie_from_direction, tie_to_direction)) = ties_map.get(&coords) {
     if direction == *tie_from_direction {
-      return (*tie_to_coords, *tie_to_direction);
+      return Ok((*tie_to_coords, *tie_to_direction));
     }
   }
 
@@ -368,14 +726,293 @@ fn find_next_rail_block(
         
         if let Some(new_rail_block) = rail_map.get(&new_coords) {
           if new_rail_block.is_rail() && new_rail_block.rail_data == new_rail_data {
-            return (new_coords, new_direction);
+            return Ok((new_coords, new_direction));
           }
         }
       }
     } else {
-      panic!("Unexpected rail data {:?} and direction {:?} at {:?}", current_rail_data, direction, coords);
+      return Err(format!("unexpected rail shape {} going {}", current_rail_data.to_str(), direction.to_str()));
+    }
+  } else {
+    return Err("no rail block".to_string());
+  }
+
+  Err("dead end".to_string())
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::common::{BlockID, Realm, SignData, EMPTY};
+  use crate::common::get_distance;
+  use crate::rail_system::validate_rail_system::validate_rail_system;
+
+  fn coords(x: i32, z: i32) -> BlockCoords {
+    (x, 64, z, Realm::Overworld)
+  }
+
+  // A rail map with a rail of the given shape at each of the given x and z coordinates.
+  fn rail_map(rails: &[((i32, i32), RailData)]) -> HashMap<BlockCoords, Block> {
+    rails
+      .iter()
+      .map(|((x, z), rail_data)| {
+        let block = Block {
+          coords: coords(*x, *z),
+          id: BlockID::UnpoweredRail,
+          rail_data: *rail_data,
+          sign_data: SignData::N,
+          sign_text: EMPTY,
+          back_sign_text: EMPTY,
+          powered: false,
+          waterlogged: false,
+        };
+        (block.coords, block)
+      })
+      .collect()
+  }
+
+  // Straight rails from one block to another in a line, both included.
+  fn straight_rails(from: (i32, i32), to: (i32, i32)) -> Vec<((i32, i32), RailData)> {
+    let rail_data = if from.0 == to.0 {RailData::NS} else {RailData::EW};
+    let (dx, dz) = ((to.0 - from.0).signum(), (to.1 - from.1).signum());
+    let length = (to.0 - from.0).abs().max((to.1 - from.1).abs());
+
+    (0..=length).map(|step| ((from.0 + dx * step, from.1 + dz * step), rail_data)).collect()
+  }
+
+  fn station(name: &str, x: i32, z: i32, direction: Direction) -> Station {
+    Station {coords: coords(x, z), name: name.to_string(), direction}
+  }
+
+  #[test]
+  fn closed_loop_is_reported_once() {
+    // The rails from the station lead north into a loop, which they join at its south west corner.
+    let mut rails = straight_rails((0, 20), (0, 9));
+    rails.push(((0, 8), RailData::NE));
+    rails.extend(straight_rails((0, 7), (0, 5)));
+    rails.push(((0, 4), RailData::SE));
+    rails.extend(straight_rails((1, 4), (4, 4)));
+    rails.push(((5, 4), RailData::SW));
+    rails.extend(straight_rails((5, 5), (5, 7)));
+    rails.push(((5, 8), RailData::NW));
+    rails.extend(straight_rails((4, 8), (1, 8)));
+
+    let stations = vec![station("Alpha", 0, 20, Direction::N)];
+    let switches = vec![];
+
+    let rail_connections = find_distances(&stations, &switches, &rail_map(&rails), &HashMap::new(), &HashMap::new());
+
+    assert_eq!(rail_connections.rail_loops.len(), 1);
+    let rail_loop = &rail_connections.rail_loops[0];
+    assert_eq!(rail_loop.start_coords, coords(0, 20));
+    assert_eq!(rail_loop.start_direction, Direction::N);
+    assert_eq!(rail_loop.loop_coords.len(), 18);
+    assert_eq!(rail_loop.loop_coords[0], coords(0, 8));
+
+    let rail_problems = validate_rail_system(
+      &stations,
+      &switches,
+      &rail_connections.rail_graph,
+      &rail_connections.distances,
+      &rail_connections.rail_loops,
+      &rail_connections.negative_cycles,
+      rail_connections.connection_problems
+    );
+    let num_loop_problems = rail_problems
+      .iter()
+      .filter(|rail_problem| rail_problem.description.contains("loop"))
+      .count();
+    assert_eq!(num_loop_problems, 1);
+  }
+
+  #[test]
+  fn figure_eight_through_switch_is_not_a_loop() {
+    // The rails leave the switch going north and come back into it going west, and leave it
+    // going south and come back into it going east, crossing at the switch.
+    let mut rails = vec![((0, 0), RailData::NE)];
+    rails.extend(straight_rails((0, -1), (0, -2)));
+    rails.push(((0, -3), RailData::SE));
+    rails.push(((1, -3), RailData::EW));
+    rails.push(((2, -3), RailData::SW));
+    rails.extend(straight_rails((2, -2), (2, -1)));
+    rails.push(((2, 0), RailData::NW));
+    rails.push(((1, 0), RailData::EW));
+    rails.extend(straight_rails((0, 1), (0, 2)));
+    rails.push(((0, 3), RailData::NW));
+    rails.push(((-1, 3), RailData::EW));
+    rails.push(((-2, 3), RailData::NE));
+    rails.extend(straight_rails((-2, 2), (-2, 1)));
+    rails.push(((-2, 0), RailData::SE));
+    rails.push(((-1, 0), RailData::EW));
+
+    let stations = vec![];
+    let switches = vec![Switch {coords: coords(0, 0), has_directions: [true, true, true, true]}];
+
+    let rail_connections = find_distances(&stations, &switches, &rail_map(&rails), &HashMap::new(), &HashMap::new());
+
+    assert!(rail_connections.rail_loops.is_empty());
+    assert!(rail_connections.connection_problems.is_empty());
+
+    // Both halves of the figure eight were followed from both ends, back to the switch.
+    for lobe_coords in [coords(2, -3), coords(-2, 3)] {
+      let num_followed = rail_connections.rail_system_coords
+        .iter()
+        .filter(|rail_coords| **rail_coords == lobe_coords)
+        .count();
+      assert_eq!(num_followed, 2);
+    }
+  }
+
+  // The shortest distances from every node to each station found by Floyd-Warshall,
+  // the way they were found before they were found per station.
+  fn floyd_warshall_station_distances(rail_graph: &RailGraph, num_stations: usize) -> Vec<i32> {
+    let num_nodes = rail_graph.len();
+    let mut all_distances: Vec<i32> = vec![i32::MAX; num_nodes * num_nodes];
+
+    for (from_node_id, node_links) in rail_graph.iter().enumerate() {
+      for rail_link in node_links {
+        all_distances[from_node_id * num_nodes + rail_link.to_node_id] = rail_link.distance;
+      }
+    }
+
+    for k in 0..num_nodes {
+      for i in 0..num_nodes {
+        for j in 0..num_nodes {
+          let ik_distance = all_distances[i * num_nodes + k];
+          let kj_distance = all_distances[k * num_nodes + j];
+
+          if ik_distance < i32::MAX && kj_distance < i32::MAX && ik_distance + kj_distance < all_distances[i * num_nodes + j] {
+            all_distances[i * num_nodes + j] = ik_distance + kj_distance;
+          }
+        }
+      }
     }
+
+    (0..num_nodes)
+      .flat_map(|node_id| (0..num_stations).map(move |station_id| (node_id, station_id)))
+      .map(|(node_id, station_id)| all_distances[node_id * num_nodes + station_id])
+      .collect()
   }
 
-  exit!("Couldn't find a rail connection from: {:?}", coords);
+  #[test]
+  fn station_distances_match_floyd_warshall() {
+    // Two switches joined by two routes of the same distance, one of them shortened by a negative
+    // weight, with a station west of the first switch, and a station east of the second switch
+    // reached through a tie.
+    let mut rails = straight_rails((-10, 0), (-1, 0));
+    rails.push(((0, 0), RailData::NE));
+    rails.extend(straight_rails((1, 0), (9, 0)));
+    rails.push(((10, 0), RailData::NE));
+    rails.extend(straight_rails((11, 0), (12, 0)));
+    rails.extend(straight_rails((30, 0), (35, 0)));
+    rails.extend(straight_rails((0, 1), (0, 4)));
+    rails.push(((0, 5), RailData::NE));
+    rails.extend(straight_rails((1, 5), (9, 5)));
+    rails.push(((10, 5), RailData::NW));
+    rails.extend(straight_rails((10, 1), (10, 4)));
+
+    let stations = vec![station("West", -10, 0, Direction::E), station("East", 35, 0, Direction::W)];
+    let switches = vec![
+      Switch {coords: coords(0, 0), has_directions: [false, true, true, true]},
+      Switch {coords: coords(10, 0), has_directions: [false, true, true, true]},
+    ];
+    let ties_map = HashMap::from([
+      (coords(12, 0), (coords(30, 0), Direction::E, Direction::E)),
+      (coords(30, 0), (coords(12, 0), Direction::W, Direction::W)),
+    ]);
+    let weights_map = HashMap::from([(coords(5, 5), -10), (coords(-5, 0), 3)]);
+
+    let rail_connections = find_distances(&stations, &switches, &rail_map(&rails), &ties_map, &weights_map);
+    let num_stations = stations.len();
+
+    assert!(rail_connections.rail_loops.is_empty());
+    assert!(rail_connections.connection_problems.is_empty());
+    assert!(rail_connections.negative_cycles.is_empty());
+
+    let distances = rail_connections.distances;
+    assert_eq!(distances, floyd_warshall_station_distances(&rail_connections.rail_graph, num_stations));
+
+    // Both routes between the switches are the same distance, so the switch is set by tie-breaking.
+    let east_station_id = 1;
+    assert_eq!(
+      get_distance(&distances, num_stations, switch_node_id(0, Direction::E as usize, num_stations), east_station_id),
+      get_distance(&distances, num_stations, switch_node_id(0, Direction::S as usize, num_stations), east_station_id)
+    );
+    assert_eq!(get_distance(&distances, num_stations, 0, east_station_id), (10 + 3 - 1) + 2 + 8 + 2 + 7);
+  }
+
+  #[test]
+  fn station_distances_with_negative_links_match_floyd_warshall() {
+    // Stations 0 to 2, and the nodes of a switch with three arms, with negative links
+    // that don't make a negative cycle, such as one-way ties with negative weights give.
+    let link = |to_node_id: usize, distance: i32| RailLink {to_node_id, distance, rail_coords: vec![]};
+    let rail_graph: RailGraph = vec![
+      vec![link(0, 0), link(3, 4)],
+      vec![link(1, 0), link(4, -3)],
+      vec![link(2, 0), link(5, 6), link(0, -2)],
+      vec![link(3, 0), link(4, 2), link(5, 2), link(0, 4)],
+      vec![link(4, 0), link(3, 2), link(5, 2), link(2, -1)],
+      vec![link(5, 0), link(3, 2), link(4, 2), link(1, 5)],
+    ];
+
+    assert!(find_negative_cycles(&rail_graph).is_empty());
+    assert_eq!(find_station_distances(&rail_graph, 3), floyd_warshall_station_distances(&rail_graph, 3));
+  }
+
+  #[test]
+  fn negative_cycle_is_reported() {
+    // The negative weight between the two stations makes going back and forth between them
+    // shorter every time.
+    let rails = straight_rails((0, 0), (10, 0));
+    let stations = vec![station("Alpha", 0, 0, Direction::E), station("Bravo", 10, 0, Direction::W)];
+    let switches = vec![];
+    let weights_map = HashMap::from([(coords(5, 0), -20), (coords(6, 0), 2)]);
+
+    let rail_connections = find_distances(&stations, &switches, &rail_map(&rails), &HashMap::new(), &weights_map);
+
+    assert_eq!(rail_connections.negative_cycles.len(), 1);
+    let negative_cycle = &rail_connections.negative_cycles[0];
+    assert_eq!(negative_cycle.node_ids, vec![0, 1]);
+    assert_eq!(negative_cycle.distance, 2 * (10 - 20 + 2));
+    assert_eq!(negative_cycle.weight_coords, vec![coords(5, 0)]);
+
+    let rail_problems = validate_rail_system(
+      &stations,
+      &switches,
+      &rail_connections.rail_graph,
+      &rail_connections.distances,
+      &rail_connections.rail_loops,
+      &rail_connections.negative_cycles,
+      rail_connections.connection_problems
+    );
+    let negative_cycle_problems: Vec<&RailProblem> = rail_problems
+      .iter()
+      .filter(|rail_problem| rail_problem.description.contains("negative weights"))
+      .collect();
+    assert_eq!(negative_cycle_problems.len(), 1);
+    assert!(negative_cycle_problems[0].is_error);
+    assert!(negative_cycle_problems[0].description.contains("(5, 64, 0, Overworld)"));
+  }
+
+  #[test]
+  fn negative_links_without_cycle_are_not_reported() {
+    // A negative weight that makes the links between the stations shorter, but not negative.
+    let rails = straight_rails((0, 0), (10, 0));
+    let stations = vec![station("Alpha", 0, 0, Direction::E), station("Bravo", 10, 0, Direction::W)];
+    let weights_map = HashMap::from([(coords(5, 0), -8)]);
+
+    let rail_connections = find_distances(&stations, &vec![], &rail_map(&rails), &HashMap::new(), &weights_map);
+    assert!(rail_connections.negative_cycles.is_empty());
+    assert_eq!(rail_connections.distances, vec![0, 2, 2, 0]);
+
+    // Negative links in one direction only, such as one-way ties with negative weights give.
+    let link = |to_node_id: usize, distance: i32| RailLink {to_node_id, distance, rail_coords: vec![]};
+    let rail_graph: RailGraph = vec![
+      vec![link(0, 0), link(1, -5)],
+      vec![link(1, 0), link(2, -5)],
+      vec![link(2, 0), link(0, 11)],
+    ];
+    assert!(find_negative_cycles(&rail_graph).is_empty());
+  }
 }
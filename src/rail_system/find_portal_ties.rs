@@ -0,0 +1,171 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::common::{
+  Block,
+  BlockCoords,
+  Direction
+};
+use crate::common::{
+  move_coords,
+  nether_portal_destination
+};
+
+
+// A tie from the rail block in front of a nether portal, to the rail block in front of the
+// portal it leads to, in the same form as the ties read from the ties file.
+pub type PortalTie = (BlockCoords, (BlockCoords, Direction, Direction));
+
+
+// A straight rail block that leads into a nether portal, the direction going into the portal,
+// and the ID of the portal.
+struct PortalRailEnd {
+  coords: BlockCoords,
+  direction: Direction,
+  portal_id: usize,
+}
+
+
+// Groups adjacent nether portal blocks into portals, and returns a map from
+// the coordinates of each nether portal block to the ID of its portal.
+fn make_portal_id_map(portal_coords: &Vec<BlockCoords>) -> HashMap<BlockCoords, usize> {
+  let portal_coords_set: HashSet<BlockCoords> = portal_coords.iter().copied().collect();
+
+  let mut portal_id_map: HashMap<BlockCoords, usize> = HashMap::new();
+  let mut num_portals = 0;
+
+  for start_coords in portal_coords {
+    if portal_id_map.contains_key(start_coords) {
+      continue;
+    }
+
+    let portal_id = num_portals;
+    num_portals += 1;
+
+    portal_id_map.insert(*start_coords, portal_id);
+    let mut coords_to_visit: Vec<BlockCoords> = vec![*start_coords];
+
+    while let Some((x, y, z, realm)) = coords_to_visit.pop() {
+      for adjacent_coords in [
+        (x + 1, y, z, realm),
+        (x - 1, y, z, realm),
+        (x, y + 1, z, realm),
+        (x, y - 1, z, realm),
+        (x, y, z + 1, realm),
+        (x, y, z - 1, realm),
+      ] {
+        if portal_coords_set.contains(&adjacent_coords) && !portal_id_map.contains_key(&adjacent_coords) {
+          portal_id_map.insert(adjacent_coords, portal_id);
+          coords_to_visit.push(adjacent_coords);
+        }
+      }
+    }
+  }
+
+  portal_id_map
+}
+
+
+// Finds the portal the game would take a minecart to, which is the portal with the nearest
+// nether portal block within the search radius of the corresponding coordinates in the other realm.
+fn find_destination_portal_id(
+  portal_coords: BlockCoords,
+  all_portal_coords: &[BlockCoords],
+  portal_id_map: &HashMap<BlockCoords, usize>
+) -> Option<usize> {
+  let ((destination_x, destination_y, destination_z, destination_realm), search_radius) =
+    nether_portal_destination(portal_coords)?;
+
+  all_portal_coords
+    .iter()
+    .filter(|(x, _, z, realm)| {
+      *realm == destination_realm &&
+        (x - destination_x).abs() <= search_radius &&
+        (z - destination_z).abs() <= search_radius
+    })
+    .min_by_key(|(x, y, z, _)| {
+      let (dx, dy, dz) = ((x - destination_x) as i64, (y - destination_y) as i64, (z - destination_z) as i64);
+      (dx * dx + dy * dy + dz * dz, *y)
+    })
+    .and_then(|coords| portal_id_map.get(coords))
+    .copied()
+}
+
+
+// Finds the straight rail blocks that lead into nether portals, and ties each one to the rail
+// block leading out of the portal that the game would take a minecart to. When the destination
+// portal has rails on more than one side, the rail leading out in the same direction that
+// the minecart went in is preferred. The ties are sorted by their "from" coordinates.
+pub fn find_portal_ties(blocks: &Vec<Block>, rail_map: &HashMap<BlockCoords, Block>) -> Vec<PortalTie> {
+  let mut all_portal_coords: Vec<BlockCoords> = blocks
+    .iter()
+    .filter(|block| block.is_nether_portal())
+    .map(|block| block.coords)
+    .collect();
+  all_portal_coords.sort();
+  all_portal_coords.dedup();
+
+  let portal_id_map = make_portal_id_map(&all_portal_coords);
+
+  let mut portal_rail_ends: Vec<PortalRailEnd> = Vec::new();
+
+  let mut rail_coords: Vec<&BlockCoords> = rail_map.keys().collect();
+  rail_coords.sort();
+
+  for coords in rail_coords {
+    let rail_block = &rail_map[coords];
+
+    let directions = match rail_block.straight_rail_directions() {
+      Some(directions) => directions,
+      None => continue,
+    };
+
+    for direction in directions {
+      if let Some(portal_id) = portal_id_map.get(&move_coords(*coords, direction)) {
+        portal_rail_ends.push(
+          PortalRailEnd {
+            coords: *coords,
+            direction,
+            portal_id: *portal_id,
+          }
+        );
+      }
+    }
+  }
+
+  let mut portal_ties: Vec<PortalTie> = Vec::new();
+
+  for from_rail_end in &portal_rail_ends {
+    let from_portal_coords = move_coords(from_rail_end.coords, from_rail_end.direction);
+
+    let destination_portal_id = match find_destination_portal_id(from_portal_coords, &all_portal_coords, &portal_id_map) {
+      Some(destination_portal_id) => destination_portal_id,
+      None => continue,
+    };
+
+    let to_rail_ends: Vec<&PortalRailEnd> = portal_rail_ends
+      .iter()
+      .filter(|rail_end| rail_end.portal_id == destination_portal_id)
+      .collect();
+
+    // The "to" direction leads away from the destination portal.
+    let to_rail_end = to_rail_ends
+      .iter()
+      .find(|rail_end| rail_end.direction.opposite_direction() == from_rail_end.direction)
+      .or(to_rail_ends.first());
+
+    if let Some(to_rail_end) = to_rail_end {
+      portal_ties.push(
+        (
+          from_rail_end.coords,
+          (
+            to_rail_end.coords,
+            from_rail_end.direction,
+            to_rail_end.direction.opposite_direction()
+          )
+        )
+      );
+    }
+  }
+
+  portal_ties
+}
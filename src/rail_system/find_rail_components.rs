@@ -0,0 +1,115 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::common::{
+  Block,
+  BlockCoords,
+  Direction,
+  Station
+};
+
+
+// The directions in which a rail block can connect to an adjacent rail block.
+// Curved rail blocks can connect in any direction, because they may be the center of a switch.
+fn rail_block_directions(rail_block: &Block) -> Vec<Direction> {
+  if rail_block.is_north_south_rail() {
+    vec![Direction::N, Direction::S]
+  } else if rail_block.is_east_west_rail() {
+    vec![Direction::W, Direction::E]
+  } else {
+    vec![Direction::N, Direction::S, Direction::W, Direction::E]
+  }
+}
+
+
+fn connected_rail_coords(
+  rail_block: &Block,
+  rail_map: &HashMap<BlockCoords, Block>,
+  tie_partners_map: &HashMap<BlockCoords, Vec<BlockCoords>>
+) -> Vec<BlockCoords> {
+  let (x, y, z, realm) = rail_block.coords;
+
+  let mut connected_coords: Vec<BlockCoords> = Vec::new();
+
+  for direction in rail_block_directions(rail_block) {
+    let (new_x, new_z) = match direction {
+      Direction::N => (x, z - 1),
+      Direction::S => (x, z + 1),
+      Direction::W => (x - 1, z),
+      Direction::E => (x + 1, z),
+    };
+
+    // The adjacent rail block may be one block higher or lower, when either one is ascending.
+    for y_offset in -1..=1 {
+      let new_coords = (new_x, y + y_offset, new_z, realm);
+
+      if let Some(new_rail_block) = rail_map.get(&new_coords) {
+        if rail_block_directions(new_rail_block).contains(&direction.opposite_direction()) {
+          connected_coords.push(new_coords);
+        }
+      }
+    }
+  }
+
+  if let Some(tie_partners) = tie_partners_map.get(&rail_block.coords) {
+    connected_coords.extend(tie_partners);
+  }
+
+  connected_coords
+}
+
+
+// Finds the groups of connected rail blocks that don't include a station, such as disconnected
+// lines, or stations with a sign that wasn't recognized. The components are sorted by decreasing
+// size, and the coordinates of each component are sorted.
+pub fn find_unconnected_rail_components(
+  stations: &Vec<Station>,
+  rail_map: &HashMap<BlockCoords, Block>,
+  ties_map: &HashMap<BlockCoords, (BlockCoords, Direction, Direction)>
+) -> Vec<Vec<BlockCoords>> {
+  // Ties connect rail blocks in both directions, even though they're followed in only one.
+  let mut tie_partners_map: HashMap<BlockCoords, Vec<BlockCoords>> = HashMap::new();
+
+  for (from_coords, (to_coords, _, _)) in ties_map {
+    tie_partners_map.entry(*from_coords).or_default().push(*to_coords);
+    tie_partners_map.entry(*to_coords).or_default().push(*from_coords);
+  }
+
+  let station_coords: HashSet<BlockCoords> = stations.iter().map(|station| station.coords).collect();
+
+  let mut all_rail_coords: Vec<BlockCoords> = rail_map.keys().copied().collect();
+  all_rail_coords.sort();
+
+  let mut visited_coords: HashSet<BlockCoords> = HashSet::new();
+
+  let mut unconnected_components: Vec<Vec<BlockCoords>> = Vec::new();
+
+  for start_coords in all_rail_coords {
+    if !visited_coords.insert(start_coords) {
+      continue;
+    }
+
+    let mut component: Vec<BlockCoords> = Vec::new();
+    let mut coords_to_visit: Vec<BlockCoords> = vec![start_coords];
+
+    while let Some(coords) = coords_to_visit.pop() {
+      component.push(coords);
+
+      if let Some(rail_block) = rail_map.get(&coords) {
+        for connected_coords in connected_rail_coords(rail_block, rail_map, &tie_partners_map) {
+          if rail_map.contains_key(&connected_coords) && visited_coords.insert(connected_coords) {
+            coords_to_visit.push(connected_coords);
+          }
+        }
+      }
+    }
+
+    if !component.iter().any(|coords| station_coords.contains(coords)) {
+      component.sort();
+      unconnected_components.push(component);
+    }
+  }
+
+  unconnected_components.sort_by(|a, b| b.len().cmp(&a.len()).then_with(|| a[0].cmp(&b[0])));
+
+  unconnected_components
+}
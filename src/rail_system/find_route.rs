@@ -0,0 +1,148 @@
+use std::collections::{HashSet};
+
+use crate::common::{
+  Direction,
+  RailGraph,
+  Route,
+  RouteStep,
+  Station,
+  Switch
+};
+use crate::common::{
+  get_distance,
+  switch_node_id
+};
+
+use crate::rail_functions::switch_functions::{shortest_switch_direction};
+
+
+// Station names are matched ignoring case, since they're sorted ignoring case.
+pub fn find_station_id_by_name(stations: &Vec<Station>, name: &str) -> Option<usize> {
+  let lower_name = name.to_lowercase();
+
+  stations
+    .iter()
+    .position(|station| station.name == name)
+    .or_else(|| stations.iter().position(|station| station.name.to_lowercase() == lower_name))
+}
+
+
+// Follows the route a minecart takes from one station to another, making the same decision
+// at each switch that the switch functions make.
+pub fn find_route(
+  from_station_id: usize,
+  to_station_id: usize,
+  stations: &Vec<Station>,
+  switches: &Vec<Switch>,
+  rail_graph: &RailGraph,
+  distances: &Vec<i32>
+) -> Result<Route, String> {
+  let num_stations = stations.len();
+
+  let weighted_distance = get_distance(distances, num_stations, from_station_id, to_station_id);
+
+  if weighted_distance == i32::MAX {
+    return Err(
+      format!("there's no route from station {:?} to station {:?}",
+              stations[from_station_id].name,
+              stations[to_station_id].name)
+    );
+  }
+
+  let mut steps: Vec<RouteStep> = Vec::new();
+  let mut rail_distance = 0;
+
+  if from_station_id == to_station_id {
+    return Ok(
+      Route {
+        from_station_id,
+        to_station_id,
+        rail_distance,
+        weighted_distance,
+        steps
+      }
+    );
+  }
+
+  // The node the minecart leaves from next, which is either a station or a switch node.
+  let mut node_id = from_station_id;
+
+  // The decision at each switch depends only on the switch node and the destination,
+  // so leaving from the same node twice means that the minecart would go around forever.
+  let mut visited_node_ids: HashSet<usize> = HashSet::new();
+
+  loop {
+    if !visited_node_ids.insert(node_id) {
+      return Err(
+        format!("the route from station {:?} to station {:?} goes around in a circle",
+                stations[from_station_id].name,
+                stations[to_station_id].name)
+      );
+    }
+
+    // Only the link along the rails has rail coordinates.
+    let rail_link = match rail_graph[node_id].iter().find(|rail_link| !rail_link.rail_coords.is_empty()) {
+      Some(rail_link) => rail_link,
+      None => {
+        return Err(format!("the rails end on the route from station {:?} to station {:?}",
+                           stations[from_station_id].name,
+                           stations[to_station_id].name));
+      }
+    };
+
+    // The rail coordinates include the rail blocks at both ends of the link.
+    rail_distance += rail_link.rail_coords.len() as i32 - 1;
+
+    for rail_coords_pair in rail_link.rail_coords.windows(2) {
+      let (from_coords, to_coords) = (rail_coords_pair[0], rail_coords_pair[1]);
+
+      if from_coords.3 != to_coords.3 {
+        steps.push(RouteStep::RealmChange(from_coords, to_coords));
+      }
+    }
+
+    let arrival_node_id = rail_link.to_node_id;
+
+    if arrival_node_id == to_station_id {
+      break;
+    }
+
+    if arrival_node_id < num_stations {
+      steps.push(RouteStep::Station(arrival_node_id));
+      node_id = arrival_node_id;
+      continue;
+    }
+
+    // The minecart arrives at the switch from the direction of the switch node it arrives at.
+    let switch_id = (arrival_node_id - num_stations) / 4;
+    let from_direction = Direction::from_usize((arrival_node_id - num_stations) % 4);
+
+    let to_direction = shortest_switch_direction(
+      &switches[switch_id],
+      switch_id,
+      from_direction,
+      to_station_id,
+      num_stations,
+      distances
+    );
+
+    if to_direction == from_direction {
+      return Err(format!("the switch at {:?} doesn't lead to station {:?}",
+                         switches[switch_id].coords,
+                         stations[to_station_id].name));
+    }
+
+    steps.push(RouteStep::Switch(switch_id, from_direction, to_direction));
+    node_id = switch_node_id(switch_id, to_direction as usize, num_stations);
+  }
+
+  Ok(
+    Route {
+      from_station_id,
+      to_station_id,
+      rail_distance,
+      weighted_distance,
+      steps
+    }
+  )
+}
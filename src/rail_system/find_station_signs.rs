@@ -73,13 +73,20 @@ pub fn find_station_signs(blocks: &Vec<Block>, stations: &Vec<Station>) -> Vec<S
           distance: 0.0
         };
 
-        if let Some(station_id) = station_name_to_id.get(&block.sign_text) {
+        // Either side of the sign can refer to a station.
+        let sign_texts = block.sign_texts();
+
+        if let Some(station_id) = sign_texts.iter().find_map(|sign_text| station_name_to_id.get(sign_text)) {
           station_sign.refers_to_station_id = *station_id;
           station_signs.push(station_sign);
           continue;
         }
 
-        station_sign.nearest_num = station_sign_nearest_num(&block.sign_text);
+        station_sign.nearest_num = sign_texts
+          .iter()
+          .map(|sign_text| station_sign_nearest_num(sign_text))
+          .find(|nearest_num| *nearest_num > 0)
+          .unwrap_or(0);
 
         if station_sign.nearest_num > 0 {
           (
@@ -13,7 +13,7 @@ fn push_station(stations: &mut Vec<Station>, rail_block: &Block, name_sign: &Blo
   stations.push(
     Station {
       coords: rail_block.coords,
-      name: name_sign.sign_text.to_string(),
+      name: name_sign.name_sign_text().to_string(),
       direction: name_sign.sign_data.to_direction(),
     }
   )
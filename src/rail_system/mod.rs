@@ -2,3 +2,7 @@ pub mod find_stations;
 pub mod find_station_signs;
 pub mod find_switches;
 pub mod find_distances;
+pub mod find_rail_components;
+pub mod find_portal_ties;
+pub mod validate_rail_system;
+pub mod find_route;
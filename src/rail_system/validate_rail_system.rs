@@ -0,0 +1,207 @@
+use std::collections::{HashMap};
+
+use crate::common::{
+  BlockCoords,
+  Direction,
+  NegativeCycle,
+  RailGraph,
+  RailLoop,
+  RailProblem,
+  Station,
+  Switch
+};
+use crate::common::{
+  get_distance,
+  switch_node_id
+};
+
+use crate::rail_functions::station_name::{station_name_rows};
+
+
+fn rail_error(coords: BlockCoords, description: String) -> RailProblem {
+  RailProblem {
+    coords,
+    is_error: true,
+    description
+  }
+}
+
+
+fn rail_warning(coords: BlockCoords, description: String) -> RailProblem {
+  RailProblem {
+    coords,
+    is_error: false,
+    description
+  }
+}
+
+
+// Follows the links of the rail graph forwards from every station.
+fn find_nodes_reachable_from_stations(rail_graph: &RailGraph, num_stations: usize) -> Vec<bool> {
+  let mut is_reachable = vec![false; rail_graph.len()];
+  let mut node_ids_to_visit: Vec<usize> = (0..num_stations).collect();
+
+  while let Some(node_id) = node_ids_to_visit.pop() {
+    if is_reachable[node_id] {
+      continue;
+    }
+    is_reachable[node_id] = true;
+
+    for rail_link in &rail_graph[node_id] {
+      node_ids_to_visit.push(rail_link.to_node_id);
+    }
+  }
+
+  is_reachable
+}
+
+
+fn describe_node(node_id: usize, stations: &[Station], switches: &[Switch]) -> String {
+  let num_stations = stations.len();
+
+  if node_id < num_stations {
+    format!("station {:?}", stations[node_id].name)
+  } else {
+    let switch_id = (node_id - num_stations) / 4;
+    let direction_index = (node_id - num_stations) % 4;
+
+    format!("switch {:?} going {}", switches[switch_id].coords, Direction::from_usize(direction_index).to_str())
+  }
+}
+
+
+fn node_coords(node_id: usize, stations: &[Station], switches: &[Switch]) -> BlockCoords {
+  let num_stations = stations.len();
+
+  if node_id < num_stations {
+    stations[node_id].coords
+  } else {
+    switches[(node_id - num_stations) / 4].coords
+  }
+}
+
+
+// Collects all of the problems with the rail system, so that they can be fixed together
+// rather than one at a time. The connection problems are the dead ends found while following
+// the rails between stations and switches.
+pub fn validate_rail_system(
+  stations: &Vec<Station>,
+  switches: &Vec<Switch>,
+  rail_graph: &RailGraph,
+  distances: &Vec<i32>,
+  rail_loops: &Vec<RailLoop>,
+  negative_cycles: &Vec<NegativeCycle>,
+  connection_problems: Vec<RailProblem>
+) -> Vec<RailProblem> {
+  let mut rail_problems: Vec<RailProblem> = connection_problems;
+
+  let num_stations = stations.len();
+
+  for rail_loop in rail_loops {
+    rail_problems.push(
+      rail_warning(
+        rail_loop.start_coords,
+        format!("the rails going {} loop through {} rail blocks without a station or switch, starting at {:?}",
+                rail_loop.start_direction.to_str(),
+                rail_loop.loop_coords.len(),
+                rail_loop.loop_coords[0])
+      )
+    );
+  }
+
+  // The shortest distances along a negative cycle are meaningless,
+  // and the switches on it would send minecarts around it forever.
+  for negative_cycle in negative_cycles {
+    let node_descriptions: Vec<String> = negative_cycle.node_ids
+      .iter()
+      .map(|node_id| describe_node(*node_id, stations, switches))
+      .collect();
+
+    let weight_coords_strs: Vec<String> = negative_cycle.weight_coords
+      .iter()
+      .map(|coords| format!("{:?}", coords))
+      .collect();
+
+    rail_problems.push(
+      rail_error(
+        negative_cycle.weight_coords
+          .first()
+          .copied()
+          .unwrap_or_else(|| node_coords(negative_cycle.node_ids[0], stations, switches)),
+        format!("the rails through {} make a cycle with a total distance of {}, because of the negative weights at [{}]",
+                node_descriptions.join(", "),
+                negative_cycle.distance,
+                weight_coords_strs.join(", "))
+      )
+    );
+  }
+
+  let mut station_ids_by_name: HashMap<String, usize> = HashMap::new();
+
+  for (station_id, station) in stations.iter().enumerate() {
+    if station_name_rows(&station.name).is_none() {
+      rail_problems.push(
+        rail_error(station.coords, format!("station name {:?} takes more than three lines", station.name))
+      );
+    }
+
+    // Station names are compared ignoring case, since they're sorted ignoring case.
+    // Stations are looked up by name, so minecarts could be sent to the wrong one of two
+    // stations with the same name.
+    if let Some(other_station_id) = station_ids_by_name.insert(station.name.to_lowercase(), station_id) {
+      rail_problems.push(
+        rail_error(station.coords,
+                   format!("station name {:?} is the same as the name of the station at {:?}",
+                           station.name,
+                           stations[other_station_id].coords))
+      );
+    }
+  }
+
+  if num_stations > 1 {
+    for (station_id, station) in stations.iter().enumerate() {
+      let is_reachable = (0..num_stations).any(|other_station_id| {
+        other_station_id != station_id &&
+          get_distance(distances, num_stations, other_station_id, station_id) < i32::MAX
+      });
+
+      if !is_reachable {
+        rail_problems.push(
+          rail_warning(station.coords, format!("station {:?} can't be reached from any other station", station.name))
+        );
+      }
+
+      let can_reach = (0..num_stations).any(|other_station_id| {
+        other_station_id != station_id &&
+          get_distance(distances, num_stations, station_id, other_station_id) < i32::MAX
+      });
+
+      if !can_reach {
+        rail_problems.push(
+          rail_warning(station.coords, format!("station {:?} can't reach any other station", station.name))
+        );
+      }
+    }
+  }
+
+  let is_reachable_from_station = find_nodes_reachable_from_stations(rail_graph, num_stations);
+
+  for (switch_id, switch) in switches.iter().enumerate() {
+    for direction_index in 0..4 { // NSWE
+      if switch.has_directions[direction_index] {
+        // A minecart arriving at the switch from this direction arrives at this switch node.
+        let node_id = switch_node_id(switch_id, direction_index, num_stations);
+
+        if !is_reachable_from_station[node_id] {
+          rail_problems.push(
+            rail_warning(switch.coords,
+                         format!("no station leads to the switch from direction {}",
+                                 Direction::from_usize(direction_index).to_str()))
+          );
+        }
+      }
+    }
+  }
+
+  rail_problems
+}
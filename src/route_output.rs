@@ -0,0 +1,119 @@
+// Prints a route between two stations, and writes it as JSON in the form:
+//
+// {
+//   "from": <station>,
+//   "to": <station>,
+//   "rail_distance": <number of rail blocks the minecart goes along>,
+//   "weighted_distance": <distance including weights, which the switches make the shortest>,
+//   "switches": [
+//     {"x": <x>, "y": <y>, "z": <z>, "realm": <realm>, "from_direction": <direction>, "to_direction": <direction>},
+//     ...
+//   ],
+//   "stations_passed": [<station>, ...],
+//   "realm_changes": [{"from": <coords>, "to": <coords>}, ...]
+// }
+//
+// where a station is {"name": <name>, "x": <x>, "y": <y>, "z": <z>, "realm": <realm>},
+// coords are {"x": <x>, "y": <y>, "z": <z>, "realm": <realm>}, the realm is "overworld",
+// "the_nether" or "the_end", and the directions are "n", "s", "w" or "e". The switches are in the
+// order they're reached, with the direction the minecart arrives from and the direction it leaves in.
+
+use serde_json::{json, Value};
+
+use crate::common::{
+  BlockCoords,
+  Route,
+  RouteStep,
+  Station,
+  Switch
+};
+use crate::common::{
+  create_and_writeln,
+  realm_to_command_realm
+};
+
+
+pub fn print_route(route: &Route, stations: &Vec<Station>, switches: &Vec<Switch>) {
+  let from_station = &stations[route.from_station_id];
+  let to_station = &stations[route.to_station_id];
+
+  println!("\nRoute from station {:?} to station {:?}, rail distance {}, weighted distance {}:",
+           from_station.name,
+           to_station.name,
+           route.rail_distance,
+           route.weighted_distance);
+  println!("Leave station {:?} at {:?} going {}", from_station.name, from_station.coords, from_station.direction.to_str());
+
+  for step in &route.steps {
+    match step {
+      RouteStep::Switch(switch_id, from_direction, to_direction) => {
+        println!("Switch at {:?}: arrive from {}, leave going {}",
+                 switches[*switch_id].coords,
+                 from_direction.to_str(),
+                 to_direction.to_str());
+      },
+      RouteStep::Station(station_id) => {
+        println!("Pass through station {:?} at {:?}", stations[*station_id].name, stations[*station_id].coords);
+      },
+      RouteStep::RealmChange(from_coords, to_coords) => {
+        println!("Go from {:?} to {:?}", from_coords, to_coords);
+      },
+    }
+  }
+
+  println!("Arrive at station {:?} at {:?}", to_station.name, to_station.coords);
+}
+
+
+fn coords_to_json(coords: BlockCoords) -> Value {
+  let (x, y, z, realm) = coords;
+  json!({"x": x, "y": y, "z": z, "realm": realm_to_command_realm(realm)})
+}
+
+
+fn station_to_json(station: &Station) -> Value {
+  let mut json_station = coords_to_json(station.coords);
+  json_station["name"] = json!(station.name);
+  json_station
+}
+
+
+pub fn write_route_json(route: &Route, stations: &Vec<Station>, switches: &Vec<Switch>, out_path: &String) {
+  let mut json_switches: Vec<Value> = Vec::new();
+  let mut json_stations_passed: Vec<Value> = Vec::new();
+  let mut json_realm_changes: Vec<Value> = Vec::new();
+
+  for step in &route.steps {
+    match step {
+      RouteStep::Switch(switch_id, from_direction, to_direction) => {
+        let mut json_switch = coords_to_json(switches[*switch_id].coords);
+        json_switch["from_direction"] = json!(from_direction.to_str());
+        json_switch["to_direction"] = json!(to_direction.to_str());
+        json_switches.push(json_switch);
+      },
+      RouteStep::Station(station_id) => {
+        json_stations_passed.push(station_to_json(&stations[*station_id]));
+      },
+      RouteStep::RealmChange(from_coords, to_coords) => {
+        json_realm_changes.push(json!({"from": coords_to_json(*from_coords), "to": coords_to_json(*to_coords)}));
+      },
+    }
+  }
+
+  let json_route = json!({
+    "from": station_to_json(&stations[route.from_station_id]),
+    "to": station_to_json(&stations[route.to_station_id]),
+    "rail_distance": route.rail_distance,
+    "weighted_distance": route.weighted_distance,
+    "switches": json_switches,
+    "stations_passed": json_stations_passed,
+    "realm_changes": json_realm_changes,
+  });
+
+  match serde_json::to_string_pretty(&json_route) {
+    Ok(json_string) => create_and_writeln(out_path, json_string),
+    Err(err) => {
+      exit!("Error converting the route to JSON: {}", err);
+    }
+  }
+}
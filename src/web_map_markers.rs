@@ -0,0 +1,374 @@
+// Writes markers for the stations, switches and rails to be shown on web maps of the world,
+// so that the rail system appears on the map every time the functions are regenerated:
+//
+// - bluemap-markers-<realm>.json has a BlueMap marker set for the map of each realm, where the
+//   realm is "overworld", "the_nether" or "the_end",
+//   in the same JSON form as BlueMap's markers.json.
+// - dynmap-markers.yml has a Dynmap marker set for all of the realms, in the form of Dynmap's
+//   markers.yml, using the default world names of each realm.
+//
+// The rails are drawn as lines between stations and switches, with only the rail blocks where
+// the rails turn or change height. The marker IDs are made from the coordinates, so they stay
+// the same when stations and switches are added or removed.
+
+use std::collections::{HashMap, HashSet};
+
+use serde_json::{json, Value};
+
+use crate::common::{
+  BlockCoords,
+  RailGraph,
+  Realm,
+  Station,
+  Switch
+};
+use crate::common::{
+  block_coords_to_file_name,
+  create_and_write,
+  escape_xml,
+  realm_to_command_realm
+};
+
+
+const MARKER_SET_ID: &str = "autorail";
+const MARKER_SET_LABEL: &str = "Rail system";
+
+const RAIL_COLOR: (u8, u8, u8) = (170, 0, 204);
+
+
+// The world names used by Dynmap on a server with the default world names.
+fn realm_to_dynmap_world(realm: Realm) -> &'static str {
+  match realm {
+    Realm::Overworld => "world",
+    Realm::Nether => "world_nether",
+    Realm::End => "world_the_end",
+  }
+}
+
+
+struct Marker {
+  id: String,
+  is_station: bool,
+  label: String,
+  detail: String,
+  coords: BlockCoords,
+}
+
+
+struct RailLine {
+  id: String,
+  label: String,
+  realm: Realm,
+  points: Vec<BlockCoords>,
+}
+
+
+fn station_markers(stations: &[Station]) -> Vec<Marker> {
+  stations
+    .iter()
+    .map(|station| {
+      let (x, y, z, _) = station.coords;
+
+      Marker {
+        id: format!("station_{}", block_coords_to_file_name(station.coords)),
+        is_station: true,
+        label: station.name.clone(),
+        detail: format!("Station {} at {} {} {}", station.name, x, y, z),
+        coords: station.coords,
+      }
+    })
+    .collect()
+}
+
+
+fn switch_markers(switches: &[Switch]) -> Vec<Marker> {
+  switches
+    .iter()
+    .map(|switch| {
+      let (x, y, z, _) = switch.coords;
+
+      let arms = switch.arms();
+      let arms_str: Vec<&str> = arms.iter().map(|direction| direction.to_str()).collect();
+
+      Marker {
+        id: format!("switch_{}", block_coords_to_file_name(switch.coords)),
+        is_station: false,
+        label: format!("Switch {} {} {}", x, y, z),
+        detail: format!("Switch at {} {} {} with arms {}", x, y, z, arms_str.join(" ")),
+        coords: switch.coords,
+      }
+    })
+    .collect()
+}
+
+
+// Keeps only the first and last rail blocks, and the rail blocks where the rails turn
+// or change height.
+fn simplify_rail_points(rail_coords: &[BlockCoords]) -> Vec<BlockCoords> {
+  let mut points: Vec<BlockCoords> = Vec::new();
+
+  for (index, coords) in rail_coords.iter().enumerate() {
+    if index == 0 || index == rail_coords.len() - 1 {
+      points.push(*coords);
+      continue;
+    }
+
+    let (prev_x, prev_y, prev_z, _) = rail_coords[index - 1];
+    let (x, y, z, _) = *coords;
+    let (next_x, next_y, next_z, _) = rail_coords[index + 1];
+
+    if (x - prev_x, y - prev_y, z - prev_z) != (next_x - x, next_y - y, next_z - z) {
+      points.push(*coords);
+    }
+  }
+
+  points
+}
+
+
+// The rails between each pair of nodes, split where they go through a nether portal into another
+// realm. The rails between two nodes are usually followed in both directions, but are drawn once.
+// Different rails can start and end at the same blocks, such as the rails either way round a loop
+// from a switch, so their IDs are numbered after the first.
+fn rail_lines(rail_graph: &RailGraph) -> Vec<RailLine> {
+  let mut drawn_rail_coords: HashSet<Vec<BlockCoords>> = HashSet::new();
+  let mut num_rail_line_ids: HashMap<String, usize> = HashMap::new();
+  let mut rail_lines: Vec<RailLine> = Vec::new();
+
+  for rail_links in rail_graph {
+    for rail_link in rail_links {
+      if rail_link.rail_coords.is_empty() {
+        continue;
+      }
+
+      let mut reversed_rail_coords = rail_link.rail_coords.clone();
+      reversed_rail_coords.reverse();
+
+      if drawn_rail_coords.contains(&reversed_rail_coords) || !drawn_rail_coords.insert(rail_link.rail_coords.clone()) {
+        continue;
+      }
+
+      for realm_rail_coords in rail_link.rail_coords.chunk_by(|(_, _, _, realm_a), (_, _, _, realm_b)| realm_a == realm_b) {
+        let first_coords = realm_rail_coords[0];
+        let last_coords = realm_rail_coords[realm_rail_coords.len() - 1];
+        let (first_x, first_y, first_z, realm) = first_coords;
+        let (last_x, last_y, last_z, _) = last_coords;
+
+        let id = format!("rails_{}_{}", block_coords_to_file_name(first_coords), block_coords_to_file_name(last_coords));
+        let num_ids = num_rail_line_ids.entry(id.clone()).or_insert(0);
+        *num_ids += 1;
+
+        rail_lines.push(
+          RailLine {
+            id: if *num_ids == 1 {id} else {format!("{}_{}", id, num_ids)},
+            label: format!("Rails from {} {} {} to {} {} {}", first_x, first_y, first_z, last_x, last_y, last_z),
+            realm,
+            points: simplify_rail_points(realm_rail_coords),
+          }
+        );
+      }
+    }
+  }
+
+  rail_lines
+}
+
+
+// https://bluemap.bluecolored.de/wiki/customization/Markers.html
+fn bluemap_marker_set(realm: Realm, markers: &[Marker], rail_lines: &[RailLine]) -> Value {
+  let mut json_markers = serde_json::Map::new();
+  let (r, g, b) = RAIL_COLOR;
+
+  for rail_line in rail_lines.iter().filter(|rail_line| rail_line.realm == realm) {
+    let (x, y, z, _) = rail_line.points[0];
+
+    let line: Vec<Value> = rail_line.points
+      .iter()
+      .map(|(x, y, z, _)| json!({"x": *x as f64 + 0.5, "y": *y as f64 + 0.5, "z": *z as f64 + 0.5}))
+      .collect();
+
+    json_markers.insert(
+      rail_line.id.clone(),
+      json!({
+        "type": "line",
+        "label": rail_line.label,
+        "position": {"x": x as f64 + 0.5, "y": y as f64 + 0.5, "z": z as f64 + 0.5},
+        "line": line,
+        "line-width": 3,
+        "line-color": {"r": r, "g": g, "b": b, "a": 1.0},
+        "depth-test": false,
+      })
+    );
+  }
+
+  for marker in markers.iter().filter(|marker| marker.coords.3 == realm) {
+    let (x, y, z, _) = marker.coords;
+
+    json_markers.insert(
+      marker.id.clone(),
+      json!({
+        "type": "poi",
+        "label": marker.label,
+        "detail": escape_xml(&marker.detail),
+        "position": {"x": x as f64 + 0.5, "y": y as f64 + 0.5, "z": z as f64 + 0.5},
+      })
+    );
+  }
+
+  json!({
+    MARKER_SET_ID: {
+      "label": MARKER_SET_LABEL,
+      "toggleable": true,
+      "default-hidden": false,
+      "markers": json_markers,
+    }
+  })
+}
+
+
+// JSON strings are also YAML strings.
+fn yaml_string(s: &str) -> String {
+  Value::String(s.to_string()).to_string()
+}
+
+
+// https://github.com/webbukkit/dynmap/wiki/Using-Markers
+fn dynmap_markers_yml(markers: &[Marker], rail_lines: &[RailLine]) -> String {
+  let (r, g, b) = RAIL_COLOR;
+
+  let mut yml = format!("sets:
+  {}:
+    label: {}
+    hide: false
+    layerprio: 0
+    markers:
+",
+                        MARKER_SET_ID,
+                        yaml_string(MARKER_SET_LABEL));
+
+  for marker in markers {
+    let (x, y, z, realm) = marker.coords;
+
+    yml.push_str(
+      &format!("      {}:
+        world: {}
+        x: {}
+        y: {}
+        z: {}
+        icon: {}
+        label: {}
+        markup: false
+",
+               marker.id,
+               realm_to_dynmap_world(realm),
+               x as f64 + 0.5,
+               y as f64,
+               z as f64 + 0.5,
+               if marker.is_station {"minecart"} else {"pin"},
+               yaml_string(&marker.label))
+    );
+  }
+
+  yml.push_str("    lines:\n");
+
+  for rail_line in rail_lines {
+    let xs: Vec<String> = rail_line.points.iter().map(|(x, _, _, _)| (*x as f64 + 0.5).to_string()).collect();
+    let ys: Vec<String> = rail_line.points.iter().map(|(_, y, _, _)| (*y as f64).to_string()).collect();
+    let zs: Vec<String> = rail_line.points.iter().map(|(_, _, z, _)| (*z as f64 + 0.5).to_string()).collect();
+
+    yml.push_str(
+      &format!("      {}:
+        world: {}
+        x: [{}]
+        y: [{}]
+        z: [{}]
+        label: {}
+        markup: false
+        strokeWeight: 3
+        strokeOpacity: 1.0
+        strokeColor: {}
+",
+               rail_line.id,
+               realm_to_dynmap_world(rail_line.realm),
+               xs.join(", "),
+               ys.join(", "),
+               zs.join(", "),
+               yaml_string(&rail_line.label),
+               (r as u32) << 16 | (g as u32) << 8 | b as u32)
+    );
+  }
+
+  yml
+}
+
+
+fn json_to_string(json_value: &Value, out_path: &String) -> String {
+  match serde_json::to_string(json_value) {
+    Ok(json_string) => json_string + "\n",
+    Err(err) => {
+      exit!("Error converting markers to JSON for file {:?}: {}", out_path, err);
+    }
+  }
+}
+
+
+pub fn write_web_map_markers(
+  stations: &Vec<Station>,
+  switches: &Vec<Switch>,
+  rail_graph: &RailGraph,
+  markers_out_path: &String
+) {
+  let mut markers = station_markers(stations);
+  markers.append(&mut switch_markers(switches));
+
+  let rail_lines = rail_lines(rail_graph);
+
+  for realm in [Realm::Overworld, Realm::Nether, Realm::End] {
+    let is_in_realm =
+      markers.iter().any(|marker| marker.coords.3 == realm) ||
+      rail_lines.iter().any(|rail_line| rail_line.realm == realm);
+
+    if !is_in_realm {
+      continue;
+    }
+
+    let bluemap_out_path = format!("{}/bluemap-markers-{}.json", markers_out_path, realm_to_command_realm(realm));
+    create_and_write(
+      &bluemap_out_path,
+      json_to_string(&bluemap_marker_set(realm, &markers, &rail_lines), &bluemap_out_path));
+  }
+
+  create_and_write(
+    &format!("{}/dynmap-markers.yml", markers_out_path),
+    dynmap_markers_yml(&markers, &rail_lines));
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::common::RailLink;
+
+  #[test]
+  fn rail_line_ids_are_unique() {
+    let around_north: Vec<BlockCoords> = vec![(0, 64, -1, Realm::Overworld), (0, 64, -2, Realm::Overworld), (1, 64, -2, Realm::Overworld), (1, 64, -1, Realm::Overworld)];
+    let around_south: Vec<BlockCoords> = vec![(0, 64, -1, Realm::Overworld), (0, 64, 0, Realm::Overworld), (1, 64, 0, Realm::Overworld), (1, 64, -1, Realm::Overworld)];
+
+    let mut reversed_around_north = around_north.clone();
+    reversed_around_north.reverse();
+
+    let rail_graph: RailGraph = vec![
+      vec![
+        RailLink {to_node_id: 1, distance: 4, rail_coords: around_north},
+        RailLink {to_node_id: 1, distance: 4, rail_coords: around_south},
+      ],
+      vec![
+        RailLink {to_node_id: 0, distance: 4, rail_coords: reversed_around_north},
+      ],
+    ];
+
+    let ids: Vec<String> = rail_lines(&rail_graph).into_iter().map(|rail_line| rail_line.id).collect();
+
+    assert_eq!(ids, vec!["rails_0_64_-1_1_64_-1", "rails_0_64_-1_1_64_-1_2"]);
+  }
+}